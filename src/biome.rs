@@ -0,0 +1,216 @@
+use noise::NoiseFn;
+
+use crate::{block::Block, generation::NoiseContext};
+
+/// Frequency of the temperature and humidity noise, in chunk units.
+const CLIMATE_FREQUENCY: f64 = 2.0e-2;
+/// How far apart two climates can be and still blend, smaller values give sharper borders.
+const BLEND_WIDTH: f64 = 0.2;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Biome {
+    #[default]
+    Plains = 0,
+    Desert,
+    Mountains,
+    Tundra,
+    Ocean,
+    Forest,
+}
+
+/// Everything world generation and meshing need to know about a biome.
+pub struct BiomeParameters {
+    /// position of the biome in (temperature, humidity) space, both in -1..1
+    climate: (f64, f64),
+    pub surface: Block,
+    pub filler: Block,
+    /// terrain height is `base + amplitude * ((noise + 1) / 2) ^ exponent`
+    base_height: f64,
+    height_amplitude: f64,
+    height_exponent: f64,
+    /// colour multiplied onto grass and foliage
+    pub tint: [f32; 3],
+}
+
+impl Biome {
+    pub const ALL: [Biome; 6] = [
+        Biome::Plains,
+        Biome::Desert,
+        Biome::Mountains,
+        Biome::Tundra,
+        Biome::Ocean,
+        Biome::Forest,
+    ];
+
+    pub fn parameters(&self) -> &'static BiomeParameters {
+        match self {
+            Biome::Plains => &BiomeParameters {
+                climate: (0.2, 0.0),
+                surface: Block::GRASS,
+                filler: Block::DIRT,
+                base_height: 20.0,
+                height_amplitude: 190.0,
+                height_exponent: 2.5,
+                tint: [0.3, 1.0, 0.15],
+            },
+            Biome::Desert => &BiomeParameters {
+                climate: (0.7, -0.6),
+                surface: Block::SAND,
+                filler: Block::SAND,
+                base_height: 22.0,
+                height_amplitude: 150.0,
+                height_exponent: 3.0,
+                tint: [0.75, 0.72, 0.3],
+            },
+            Biome::Mountains => &BiomeParameters {
+                climate: (-0.3, -0.5),
+                surface: Block::STONE,
+                filler: Block::STONE,
+                base_height: 30.0,
+                height_amplitude: 220.0,
+                height_exponent: 1.6,
+                tint: [0.45, 0.7, 0.4],
+            },
+            Biome::Tundra => &BiomeParameters {
+                climate: (-0.7, 0.1),
+                surface: Block::SNOW,
+                filler: Block::DIRT,
+                base_height: 20.0,
+                height_amplitude: 180.0,
+                height_exponent: 2.5,
+                tint: [0.5, 0.75, 0.6],
+            },
+            Biome::Ocean => &BiomeParameters {
+                climate: (0.0, 0.9),
+                surface: Block::SAND,
+                filler: Block::SAND,
+                base_height: 5.0,
+                height_amplitude: 120.0,
+                height_exponent: 2.5,
+                tint: [0.25, 0.8, 0.3],
+            },
+            Biome::Forest => &BiomeParameters {
+                climate: (0.2, 0.55),
+                surface: Block::GRASS,
+                filler: Block::DIRT,
+                base_height: 22.0,
+                height_amplitude: 190.0,
+                height_exponent: 2.3,
+                tint: [0.2, 0.8, 0.1],
+            },
+        }
+    }
+}
+
+/// Biome weights of a single column, summing to one.
+pub struct BiomeBlend {
+    weights: [f64; Biome::ALL.len()],
+}
+
+impl BiomeBlend {
+    /// Blend at a column, `x` and `z` are in chunk units.
+    pub fn at(noise: &NoiseContext, x: f64, z: f64) -> Self {
+        let temperature = noise.temperature.get([CLIMATE_FREQUENCY * x, CLIMATE_FREQUENCY * z]);
+        let humidity = noise.humidity.get([CLIMATE_FREQUENCY * x, CLIMATE_FREQUENCY * z]);
+
+        let mut weights = Biome::ALL.map(|biome| {
+            let (t, h) = biome.parameters().climate;
+            let distance_squared = (temperature - t).powi(2) + (humidity - h).powi(2);
+            (-distance_squared / (BLEND_WIDTH * BLEND_WIDTH)).exp()
+        });
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            weights.iter_mut().for_each(|weight| *weight /= total);
+        } else {
+            // far from every climate, fall back to the closest biome
+            weights = [0.0; Biome::ALL.len()];
+            let closest = Biome::ALL
+                .iter()
+                .map(|biome| {
+                    let (t, h) = biome.parameters().climate;
+                    (temperature - t).powi(2) + (humidity - h).powi(2)
+                })
+                .enumerate()
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap()
+                .0;
+            weights[closest] = 1.0;
+        }
+        Self { weights }
+    }
+
+    /// The biome with the largest weight, used to pick surface blocks.
+    pub fn dominant(&self) -> Biome {
+        let index = self
+            .weights
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0;
+        Biome::ALL[index]
+    }
+
+    /// Terrain height from the height noise in -1..1, blended between biome height curves.
+    pub fn height(&self, height_noise: f64) -> usize {
+        let normalized = ((height_noise + 1.0) / 2.0).clamp(0.0, 1.0);
+        let height: f64 = Biome::ALL
+            .iter()
+            .zip(self.weights)
+            .map(|(biome, weight)| {
+                let parameters = biome.parameters();
+                weight
+                    * (parameters.base_height
+                        + parameters.height_amplitude * normalized.powf(parameters.height_exponent))
+            })
+            .sum();
+        height.floor().clamp(1.0, 255.0) as usize
+    }
+
+    pub fn tint(&self) -> [f32; 3] {
+        let mut tint = [0.0; 3];
+        for (biome, weight) in Biome::ALL.iter().zip(self.weights) {
+            for (channel, biome_channel) in tint.iter_mut().zip(biome.parameters().tint) {
+                *channel += biome_channel * weight as f32;
+            }
+        }
+        tint
+    }
+}
+
+/// Per column biome ids and blended tints of a chunk.
+#[derive(Debug, Clone)]
+pub struct ChunkBiomes {
+    pub ids: [[Biome; 16]; 16],
+    pub tints: [[[f32; 3]; 16]; 16],
+}
+
+impl Default for ChunkBiomes {
+    fn default() -> Self {
+        Self {
+            ids: [[Biome::default(); 16]; 16],
+            tints: [[Biome::default().parameters().tint; 16]; 16],
+        }
+    }
+}
+
+impl ChunkBiomes {
+    /// Biomes are a pure function of the seed and column, so chunks loaded from disk
+    /// recompute them instead of storing them.
+    pub fn generate(noise: &NoiseContext, position: (i64, i64)) -> Self {
+        let mut biomes = Self::default();
+        for x in 0..16 {
+            for z in 0..16 {
+                let blend = BiomeBlend::at(
+                    noise,
+                    x as f64 / 16.0 + position.0 as f64,
+                    z as f64 / 16.0 + position.1 as f64,
+                );
+                biomes.ids[x][z] = blend.dominant();
+                biomes.tints[x][z] = blend.tint();
+            }
+        }
+        biomes
+    }
+}
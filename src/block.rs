@@ -1,46 +1,302 @@
-use crate::{Cardinal, texture::TextureManager};
-use std::sync::Arc;
+use crate::{fluid::{Fluid, FULL}, texture::TextureManager, Cardinal};
+use anyhow::*;
+use serde::Deserialize;
+use std::{collections::HashMap, fs, path::Path};
 
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
-pub enum Block {
+/// Numeric block id. What a block looks like and how it behaves is defined in the
+/// block registry file, the constants below are the blocks world generation uses.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+pub struct Block(pub u8);
+
+impl Block {
+    pub const AIR: Block = Block(0);
+    pub const GRASS: Block = Block(1);
+    pub const DIRT: Block = Block(2);
+    pub const STONE: Block = Block(3);
+    pub const COBBLESTONE: Block = Block(4);
+    pub const BEDROCK: Block = Block(5);
+    pub const SAND: Block = Block(6);
+    pub const SNOW: Block = Block(7);
+    pub const COAL_ORE: Block = Block(8);
+    pub const IRON_ORE: Block = Block(9);
+    pub const COPPER_ORE: Block = Block(10);
+    pub const GOLD_ORE: Block = Block(11);
+    pub const DIAMOND_ORE: Block = Block(12);
+    pub const LOG: Block = Block(13);
+    pub const LEAVES: Block = Block(14);
+    pub const WATER: Block = Block(16);
+    pub const GRAVEL: Block = Block(17);
+    pub const LAVA: Block = Block(18);
+
+    /// Blocks referenced from Rust code, the registry has to define each of them under
+    /// this name.
+    pub const BUILTIN: [(Block, &'static str); 18] = [
+        (Block::AIR, "air"),
+        (Block::GRASS, "grass"),
+        (Block::DIRT, "dirt"),
+        (Block::STONE, "stone"),
+        (Block::COBBLESTONE, "cobblestone"),
+        (Block::BEDROCK, "bedrock"),
+        (Block::SAND, "sand"),
+        (Block::SNOW, "snow"),
+        (Block::COAL_ORE, "coal_ore"),
+        (Block::IRON_ORE, "iron_ore"),
+        (Block::COPPER_ORE, "copper_ore"),
+        (Block::GOLD_ORE, "gold_ore"),
+        (Block::DIAMOND_ORE, "diamond_ore"),
+        (Block::LOG, "log"),
+        (Block::LEAVES, "leaves"),
+        (Block::WATER, "water"),
+        (Block::GRAVEL, "gravel"),
+        (Block::LAVA, "lava"),
+    ];
+}
+
+/// Which faces of a block are coloured with the biome tint of their column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tint {
+    #[default]
+    None,
+    Top,
+    All,
+}
+
+/// How light passes through a block, decides which faces are drawn and in which pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transparency {
+    /// hides the faces of its neighbours
     #[default]
-    Air = 0,
-    Grass,
-    Dirt,
-    Stone,
-    Cobblestone,
-    Bedrock,
-}
-
-
-pub async fn get_block_texture_ids(block: Block, texture_manager: Arc<TextureManager>) -> [f32; 6] {
-    let mut indexes = [0.; 6];
-    match block {
-        Block::Grass => {
-            indexes[Cardinal::Up as usize] = texture_manager.get_id("grass_top".into());
-            indexes[Cardinal::Down as usize] = texture_manager.get_id("dirt".into());
-
-            indexes[Cardinal::North as usize..=Cardinal::West as usize]
-            .iter_mut()
-            .for_each(
-                |x| 
-                *x = texture_manager.get_id("grass_side".into())
-            );
-        },
-        Block::Dirt => {
-            indexes = [texture_manager.get_id("dirt".into()); 6];
-        },
-        Block::Cobblestone => {
-            indexes = [texture_manager.get_id("cobblestone".into()); 6];
-        },
-        Block::Stone => {
-            indexes = [texture_manager.get_id("stone".into()); 6];
-        },
-        Block::Bedrock => {
-            indexes = [texture_manager.get_id("bedrock".into()); 6];
+    Opaque,
+    /// fully opaque or fully transparent texels, drawn with alpha testing (leaves, glass)
+    Cutout,
+    /// blended with what is behind it (water), drawn after everything else
+    Translucent,
+}
+
+/// Texture names of a block as written in the registry file. `all` applies to every
+/// face, `side` to the four horizontal faces, and single faces override both.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureNames {
+    all: Option<String>,
+    side: Option<String>,
+    top: Option<String>,
+    bottom: Option<String>,
+    north: Option<String>,
+    south: Option<String>,
+    east: Option<String>,
+    west: Option<String>,
+}
+
+impl TextureNames {
+    /// Texture name of every face, indexed by `Cardinal`.
+    fn resolve(&self) -> Option<[String; 6]> {
+        let side = self.side.as_ref().or(self.all.as_ref());
+        let faces = [
+            self.top.as_ref().or(self.all.as_ref()),
+            self.bottom.as_ref().or(self.all.as_ref()),
+            self.north.as_ref().or(side),
+            self.south.as_ref().or(side),
+            self.east.as_ref().or(side),
+            self.west.as_ref().or(side),
+        ];
+        if faces.iter().any(Option::is_none) {
+            return None;
+        }
+        Some(faces.map(|face| face.unwrap().clone()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockEntry {
+    id: u8,
+    name: String,
+    /// blocks without textures are never meshed
+    textures: Option<TextureNames>,
+    #[serde(default = "default_true")]
+    solid: bool,
+    #[serde(default)]
+    transparency: Transparency,
+    #[serde(default)]
+    hardness: f32,
+    #[serde(default)]
+    tint: Tint,
+    #[serde(default)]
+    falls: bool,
+    fluid: Option<Fluid>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryFile {
+    block: Vec<BlockEntry>,
+}
+
+/// Everything the game knows about one block id.
+#[derive(Debug, Clone)]
+pub struct BlockDefinition {
+    pub name: String,
+    /// texture ids indexed by `Cardinal`, `None` for blocks that are not drawn
+    pub textures: Option<[f32; 6]>,
+    pub solid: bool,
+    pub transparency: Transparency,
+    pub hardness: f32,
+    pub tint: Tint,
+    /// falls when the block below does not hold it up, like sand
+    pub falls: bool,
+    pub fluid: Option<Fluid>,
+}
+
+impl BlockDefinition {
+    /// Whether a face is coloured with the biome tint of its column.
+    pub fn is_tinted(&self, face: Cardinal) -> bool {
+        match self.tint {
+            Tint::None => false,
+            Tint::Top => face == Cardinal::Up,
+            Tint::All => true,
         }
-        _ => {}
     }
-    indexes
+
+    /// Whether the block hides the faces of its neighbours. Blocks that are not drawn
+    /// never do.
+    pub fn is_opaque(&self) -> bool {
+        self.textures.is_some() && self.transparency == Transparency::Opaque
+    }
+}
+
+/// Block definitions loaded from the registry file, indexed by block id.
+#[derive(Debug)]
+pub struct BlockRegistry {
+    definitions: Vec<Option<BlockDefinition>>,
+    /// block id of every fluid level the registry defines
+    fluids: HashMap<Fluid, Block>,
+    unknown: BlockDefinition,
+}
+
+impl BlockRegistry {
+    /// Loads the registry and resolves every texture name against the textures found by
+    /// the texture manager.
+    pub fn load<P: AsRef<Path>>(path: P, texture_manager: &TextureManager) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).with_context(|| format!("reading block registry {:?}", path))?;
+        Self::parse(&text, |name| texture_manager.get_id(name))
+            .with_context(|| format!("loading block registry {:?}", path))
+    }
+
+    /// Parses a registry file, `texture_id` maps a texture name to its id.
+    pub fn parse(text: &str, texture_id: impl Fn(&str) -> Option<f32>) -> Result<Self> {
+        let file: RegistryFile = toml::from_str(text)?;
+
+        let mut definitions: Vec<Option<BlockDefinition>> = vec![None; 256];
+        let mut names = HashMap::new();
+        let mut fluids = HashMap::new();
+        for entry in file.block {
+            if let Some(other) = names.insert(entry.name.clone(), entry.id) {
+                bail!("block name \"{}\" is used by ids {} and {}", entry.name, other, entry.id);
+            }
+            if let Some(other) = &definitions[entry.id as usize] {
+                bail!("block id {} is used by \"{}\" and \"{}\"", entry.id, other.name, entry.name);
+            }
+            if let Some(fluid) = entry.fluid {
+                if !(1..=FULL).contains(&fluid.level) || fluid.source && fluid.level != FULL {
+                    bail!("block \"{}\" has an invalid fluid level {}", entry.name, fluid.level);
+                }
+                if let Some(other) = fluids.insert(fluid, Block(entry.id)) {
+                    bail!("blocks {} and {} are the same fluid {:?}", other.0, entry.id, fluid);
+                }
+            }
+            let textures = match &entry.textures {
+                Some(textures) => {
+                    let Some(names) = textures.resolve() else {
+                        bail!("block \"{}\" does not name a texture for every face", entry.name);
+                    };
+                    let mut ids = [0.0; 6];
+                    for (id, name) in ids.iter_mut().zip(&names) {
+                        *id = texture_id(name)
+                            .ok_or_else(|| anyhow!("block \"{}\" uses unknown texture \"{}\"", entry.name, name))?;
+                    }
+                    Some(ids)
+                }
+                None => None,
+            };
+            definitions[entry.id as usize] = Some(BlockDefinition {
+                name: entry.name,
+                textures,
+                solid: entry.solid,
+                transparency: entry.transparency,
+                hardness: entry.hardness,
+                tint: entry.tint,
+                falls: entry.falls,
+                fluid: entry.fluid,
+            });
+        }
+
+        for (block, name) in Block::BUILTIN {
+            match &definitions[block.0 as usize] {
+                Some(definition) if definition.name == name => {}
+                Some(definition) => bail!(
+                    "block id {} must be \"{}\" but is defined as \"{}\"",
+                    block.0,
+                    name,
+                    definition.name
+                ),
+                None => bail!("built in block \"{}\" (id {}) is not defined", name, block.0),
+            }
+        }
+
+        Ok(Self {
+            definitions,
+            fluids,
+            unknown: BlockDefinition {
+                name: "unknown".into(),
+                textures: None,
+                solid: true,
+                transparency: Transparency::Opaque,
+                hardness: 0.0,
+                tint: Tint::None,
+                falls: false,
+                fluid: None,
+            },
+        })
+    }
+
+    /// Definition of a block, ids missing from the registry get an invisible solid block.
+    pub fn get(&self, block: Block) -> &BlockDefinition {
+        self.definitions[block.0 as usize].as_ref().unwrap_or(&self.unknown)
+    }
+
+    /// Whether `face` of `block`, which touches `neighbour`, has to be drawn. Faces
+    /// against opaque blocks are hidden, and so are faces between two translucent
+    /// blocks of the same type so a body of water has no inner walls. Between two
+    /// levels of a fluid only the sides of the higher level show.
+    pub fn is_face_visible(&self, block: Block, neighbour: Block, face: Cardinal) -> bool {
+        if self.get(neighbour).is_opaque() {
+            return false;
+        }
+        if let (Some(fluid), Some(other)) = (self.get(block).fluid, self.get(neighbour).fluid) {
+            if fluid.kind == other.kind {
+                return !matches!(face, Cardinal::Up | Cardinal::Down) && other.level < fluid.level;
+            }
+        }
+        block != neighbour || self.get(block).transparency != Transparency::Translucent
+    }
+
+    /// Block id of a fluid level, `None` if the registry does not define it.
+    pub fn fluid_block(&self, fluid: Fluid) -> Option<Block> {
+        self.fluids.get(&fluid).copied()
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<Block> {
+        self.definitions
+            .iter()
+            .position(|definition| definition.as_ref().is_some_and(|definition| definition.name == name))
+            .map(|id| Block(id as u8))
+    }
 }
@@ -0,0 +1,236 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet, VecDeque},
+};
+
+use crate::{
+    block::{Block, BlockRegistry},
+    world::World,
+    Cardinal,
+};
+
+/// Ticks a falling block waits before it drops by one block.
+pub const FALL_DELAY: u64 = 2;
+
+/// Block updates waiting to run, advanced one tick at a time by `World::tick_blocks`
+/// with the tick counted by `Time`.
+///
+/// Blocks are told when a neighbour changes, and can ask to be updated again a number
+/// of ticks later. Both run in a fixed order, notifications in the order the changes
+/// happened and scheduled updates by tick and then in the order they were scheduled,
+/// so the same changes always play out the same way.
+#[derive(Debug, Default)]
+pub struct BlockUpdates {
+    /// the tick running or last run, as counted by `Time::tick`
+    tick: u64,
+    /// blocks next to a change since the last tick, oldest first
+    notified: VecDeque<[i64; 3]>,
+    /// updates by the tick they are due in and the order they were scheduled in
+    scheduled: BinaryHeap<Reverse<(u64, u64, [i64; 3])>>,
+    /// positions and ticks in `scheduled`, so a block is updated at most once a tick
+    pending: HashSet<([i64; 3], u64)>,
+    /// updates scheduled so far
+    order: u64,
+}
+
+impl BlockUpdates {
+    /// Tells the block at `position` and its six neighbours that it changed.
+    pub fn notify(&mut self, position: [i64; 3]) {
+        self.notified.push_back(position);
+        for face in Cardinal::ALL {
+            let [dx, dy, dz] = face.normal();
+            self.notified.push_back([position[0] + dx, position[1] + dy, position[2] + dz]);
+        }
+    }
+
+    /// Updates the block at `position` in `delay` ticks, at least the next one.
+    pub fn schedule(&mut self, position: [i64; 3], delay: u64) {
+        let due = self.tick + delay.max(1);
+        if self.pending.insert((position, due)) {
+            self.scheduled.push(Reverse((due, self.order, position)));
+            self.order += 1;
+        }
+    }
+
+    /// Whether nothing is waiting to run.
+    pub fn is_empty(&self) -> bool {
+        self.notified.is_empty() && self.scheduled.is_empty()
+    }
+
+    /// Takes the next update due in the current tick.
+    fn next_due(&mut self) -> Option<[i64; 3]> {
+        let Reverse((due, _, position)) = *self.scheduled.peek()?;
+        if due > self.tick {
+            return None;
+        }
+        self.scheduled.pop();
+        self.pending.remove(&(position, due));
+        Some(position)
+    }
+}
+
+impl World {
+    /// Runs tick `tick` of block updates. The updates scheduled up to this tick go
+    /// first, then every block notified since the last tick reacts to its neighbours.
+    /// Blocks in chunks that are not in memory ignore their updates.
+    pub fn tick_blocks(&mut self, registry: &BlockRegistry, tick: u64) {
+        debug_assert!(tick >= self.updates.tick, "ticks run in order");
+        self.updates.tick = tick;
+        while let Some(position) = self.updates.next_due() {
+            self.scheduled_update(registry, position);
+        }
+        while let Some(position) = self.updates.notified.pop_front() {
+            self.neighbour_changed(registry, position);
+        }
+    }
+
+    fn neighbour_changed(&mut self, registry: &BlockRegistry, position: [i64; 3]) {
+        let Some(block) = self.loaded_block(position) else {
+            return;
+        };
+        if let Some(fluid) = registry.get(block).fluid {
+            self.fluid_neighbour_changed(registry, position, fluid);
+        } else if registry.get(block).falls && self.can_fall(registry, position) {
+            self.updates.schedule(position, FALL_DELAY);
+        }
+    }
+
+    fn scheduled_update(&mut self, registry: &BlockRegistry, position: [i64; 3]) {
+        let Some(block) = self.loaded_block(position) else {
+            return;
+        };
+        if let Some(fluid) = registry.get(block).fluid {
+            self.flow(registry, position, fluid);
+        } else if registry.get(block).falls && self.can_fall(registry, position) {
+            // the blocks set notify their neighbours, which schedules the next drop
+            let [x, y, z] = position;
+            self.set_block(position, Block::AIR);
+            self.set_block([x, y - 1, z], block);
+        }
+    }
+
+    /// Whether the block below `position` is loaded and does not hold it up. Blocks
+    /// that are not solid, like water, are replaced by whatever falls into them.
+    fn can_fall(&self, registry: &BlockRegistry, [x, y, z]: [i64; 3]) -> bool {
+        y > 0 && self.loaded_block([x, y - 1, z]).is_some_and(|below| !registry.get(below).solid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fluid::FluidKind,
+        test_util::{floor_world, registry},
+        time::Time,
+    };
+
+    fn run(world: &mut World, registry: &BlockRegistry, time: &mut Time, ticks: usize) {
+        for _ in 0..ticks {
+            world.tick_blocks(registry, time.tick);
+            time.finish_tick();
+        }
+    }
+
+    #[test]
+    fn sand_falls_one_block_every_fall_delay() {
+        let registry = registry();
+        let mut world = floor_world(0);
+        let mut time = Time::new();
+        world.set_block([5, 20, 5], Block::SAND);
+
+        // the first tick notices the sand can fall, which it does FALL_DELAY ticks later
+        run(&mut world, &registry, &mut time, FALL_DELAY as usize);
+        assert_eq!(world.loaded_block([5, 20, 5]), Some(Block::SAND));
+        run(&mut world, &registry, &mut time, 1);
+        assert_eq!(world.loaded_block([5, 20, 5]), Some(Block::AIR));
+        assert_eq!(world.loaded_block([5, 19, 5]), Some(Block::SAND));
+        run(&mut world, &registry, &mut time, FALL_DELAY as usize - 1);
+        assert_eq!(world.loaded_block([5, 19, 5]), Some(Block::SAND));
+        run(&mut world, &registry, &mut time, 1);
+        assert_eq!(world.loaded_block([5, 18, 5]), Some(Block::SAND));
+
+        run(&mut world, &registry, &mut time, 100);
+        assert_eq!(world.loaded_block([5, 11, 5]), Some(Block::SAND));
+        assert_eq!(world.loaded_block([5, 10, 5]), Some(Block::STONE));
+        assert!(world.updates.is_empty());
+    }
+
+    #[test]
+    fn sand_sinks_through_water() {
+        let registry = registry();
+        let mut world = floor_world(0);
+        let mut time = Time::new();
+        // a pool 3 deep with stone walls
+        for x in 3..8 {
+            for z in 3..8 {
+                for y in 11..14 {
+                    let wall = x == 3 || x == 7 || z == 3 || z == 7;
+                    world.set_block([x, y, z], if wall { Block::STONE } else { Block::WATER });
+                }
+            }
+        }
+        world.set_block([5, 20, 5], Block::SAND);
+        run(&mut world, &registry, &mut time, 600);
+
+        assert_eq!(world.loaded_block([5, 11, 5]), Some(Block::SAND));
+        let sand = (11..=20).filter(|y| world.loaded_block([5, *y, 5]) == Some(Block::SAND)).count();
+        assert_eq!(sand, 1);
+        // the water it pushed aside flows back over it
+        let above = registry.get(world.loaded_block([5, 12, 5]).unwrap());
+        assert_eq!(above.fluid.map(|fluid| fluid.kind), Some(FluidKind::Water));
+    }
+
+    #[test]
+    fn sand_on_the_ground_stays() {
+        let registry = registry();
+        let mut world = floor_world(0);
+        let mut time = Time::new();
+        world.set_block([5, 11, 5], Block::SAND);
+        run(&mut world, &registry, &mut time, 10);
+        assert_eq!(world.loaded_block([5, 11, 5]), Some(Block::SAND));
+        assert!(world.updates.is_empty());
+    }
+
+    #[test]
+    fn scheduled_updates_run_by_tick_then_in_order() {
+        let mut updates = BlockUpdates::default();
+        updates.schedule([0, 0, 0], 3);
+        updates.schedule([1, 0, 0], 1);
+        updates.schedule([2, 0, 0], 3);
+        updates.schedule([3, 0, 0], 1);
+        // once per block and tick
+        updates.schedule([1, 0, 0], 1);
+
+        let mut order = vec![];
+        for tick in 0..4 {
+            updates.tick = tick;
+            let mut due = vec![];
+            while let Some(position) = updates.next_due() {
+                due.push(position[0]);
+            }
+            order.push(due);
+        }
+        assert_eq!(order, [vec![], vec![1, 3], vec![], vec![0, 2]]);
+        assert!(updates.is_empty());
+    }
+
+    #[test]
+    fn same_changes_play_out_the_same() {
+        let registry = registry();
+        let play = || {
+            let mut world = floor_world(0);
+            for (i, x) in (2..14).enumerate() {
+                world.set_block([x, 15 + i as i64 % 4, 8], Block::SAND);
+                world.set_block([x, 11, 2 + i as i64 % 3], Block::WATER);
+            }
+            let mut states = vec![];
+            for tick in 0..40 {
+                world.tick_blocks(&registry, tick);
+                states.push(world.chunks[&(0, 0)].to_bytes());
+            }
+            states
+        };
+        assert_eq!(play(), play());
+    }
+}
@@ -4,6 +4,8 @@ use winit::dpi::PhysicalPosition;
 use winit::event::*;
 use winit::keyboard::{KeyCode, PhysicalKey};
 
+use crate::player::Player;
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -14,7 +16,7 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub position: Point3<f32>,
     yaw: Rad<f32>,
@@ -34,13 +36,25 @@ impl Camera {
         }
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
+    pub fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+
+    /// Unit vector the camera looks along.
+    pub fn forward(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
 
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
         Matrix4::look_to_rh(
             self.position,
-            Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
+            self.forward(),
             Vector3::unit_y(),
         )
     }
@@ -53,6 +67,12 @@ pub struct CameraUniform {
     view_position: [f32; 4],
 }
 
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CameraUniform {
     pub fn new() -> Self {
         use cgmath::SquareMatrix;
@@ -187,6 +207,7 @@ impl CameraController {
         };
     }
 
+    /// Moves the camera freely, through terrain.
     pub fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
         // Move forward/backward and left/right
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
@@ -199,7 +220,7 @@ impl CameraController {
         let right = right * (self.amount_right - self.amount_left);
         let mut dir = forward + right;
         if (self.amount_backward != 0.0 || self.amount_forward != 0.0) && (self.amount_left != 0.0 || self.amount_right != 0.0) {
-            dir = dir / 2_f32.sqrt();
+            dir /= 2_f32.sqrt();
         } 
         camera.position += dir * speed * dt;
 
@@ -216,8 +237,28 @@ impl CameraController {
         // Move up/down. Since we don't use roll, we can just
         // modify the y coordinate directly.
         camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+    }
+
+    /// Moves the camera as a walking player, falling and colliding with the blocks
+    /// `solid` accepts, see `Player::step`. Space jumps.
+    pub fn update_walking(
+        &mut self,
+        camera: &mut Camera,
+        player: &mut Player,
+        dt: f32,
+        solid: impl Fn([i64; 3]) -> bool,
+    ) {
+        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
+        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin) * (self.amount_forward - self.amount_backward);
+        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos) * (self.amount_right - self.amount_left);
+        let walk = forward + right;
+        let walk = if walk.magnitude2() > 0.0 { walk.normalize() } else { walk };
+        camera.position = player.step(camera.position, walk, self.amount_up > 0.0, dt, solid);
+        self.scroll = 0.0;
+    }
 
-        // Rotate
+    /// Turns the camera by the mouse movement since the last call.
+    pub fn rotate_camera(&mut self, camera: &mut Camera, dt: f32) {
         camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
         camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
 
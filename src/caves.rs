@@ -0,0 +1,244 @@
+use noise::NoiseFn;
+use rand::Rng;
+
+use crate::{block::Block, generation::NoiseContext, world::Chunk};
+
+/// Lowest layer caves may carve, everything below stays solid so caves never open
+/// into the bedrock floor.
+pub const MIN_CAVE_HEIGHT: usize = 2;
+
+/// Frequency of the large "cheese" cave noise, in blocks.
+const CHEESE_FREQUENCY: f64 = 1.2e-2;
+/// Noise value above which cheese caves are hollowed out.
+const CHEESE_THRESHOLD: f64 = 0.55;
+/// Cheese caves stay this many blocks below the surface so they do not riddle it.
+const CHEESE_SURFACE_MARGIN: usize = 10;
+
+/// Frequency of the two noise fields whose zero crossings form "spaghetti" tunnels.
+const SPAGHETTI_FREQUENCY: f64 = 2.5e-2;
+/// Half width of the tunnels in noise space.
+const SPAGHETTI_WIDTH: f64 = 0.045;
+
+/// Chance that a chunk starts a worm carver.
+const CARVER_CHANCE: f64 = 0.15;
+/// Steps a worm takes, one block each.
+const CARVER_STEPS: usize = 96;
+/// Largest base radius of a worm. The radius peaks at one block more than its base.
+const CARVER_MAX_BASE_RADIUS: f64 = 3.5;
+/// How many chunks away a worm carver can start and still reach into a chunk. Worms
+/// start anywhere in their chunk, so one more than the chunks a worm spans from its
+/// start.
+const CARVER_RANGE: i64 = (CARVER_STEPS as f64 + 1.0 + CARVER_MAX_BASE_RADIUS) as i64 / 16 + 1;
+
+/// Carves noise caves and worm carvers out of the terrain of a chunk. `heights` is the
+/// terrain height of every column.
+///
+/// Everything here only depends on world coordinates and the seed, so neighbouring
+/// chunks agree on the caves that cross their shared border.
+pub fn carve(noise: &NoiseContext, chunk: &mut Chunk, position: (i64, i64), heights: &[[usize; 16]; 16]) {
+    carve_noise_caves(noise, chunk, position, heights);
+    carve_worms(noise, chunk, position);
+}
+
+fn carve_noise_caves(noise: &NoiseContext, chunk: &mut Chunk, position: (i64, i64), heights: &[[usize; 16]; 16]) {
+    for (x, column) in heights.iter().enumerate() {
+        for (z, &height) in column.iter().enumerate() {
+            let world_x = (position.0 * 16 + x as i64) as f64;
+            let world_z = (position.1 * 16 + z as i64) as f64;
+            for y in MIN_CAVE_HEIGHT..=height {
+                if chunk.get_block(x, y, z) == Block::AIR {
+                    continue;
+                }
+                let world_y = y as f64;
+
+                let cheese = y + CHEESE_SURFACE_MARGIN <= height
+                    && noise.caves[0].get([
+                        CHEESE_FREQUENCY * world_x,
+                        CHEESE_FREQUENCY * 1.5 * world_y,
+                        CHEESE_FREQUENCY * world_z,
+                    ]) > CHEESE_THRESHOLD;
+                let spaghetti = || {
+                    let point = [
+                        SPAGHETTI_FREQUENCY * world_x,
+                        SPAGHETTI_FREQUENCY * world_y,
+                        SPAGHETTI_FREQUENCY * world_z,
+                    ];
+                    noise.caves[1].get(point).abs() < SPAGHETTI_WIDTH
+                        && noise.caves[2].get(point).abs() < SPAGHETTI_WIDTH
+                };
+                if cheese || spaghetti() {
+                    chunk.set_block(x, y, z, Block::AIR);
+                }
+            }
+        }
+    }
+}
+
+/// Worm carvers are random walks that hollow out spheres along their path. Every
+/// chunk within `CARVER_RANGE` is checked for a worm starting in it, and the parts of
+/// those worms that fall inside this chunk are carved.
+fn carve_worms(noise: &NoiseContext, chunk: &mut Chunk, position: (i64, i64)) {
+    for origin_x in position.0 - CARVER_RANGE..=position.0 + CARVER_RANGE {
+        for origin_z in position.1 - CARVER_RANGE..=position.1 + CARVER_RANGE {
+            for (center, radius) in worm(noise, (origin_x, origin_z)) {
+                carve_sphere(chunk, position, center, radius);
+            }
+        }
+    }
+}
+
+/// Centres and radii of the spheres carved by the worm starting in the chunk at
+/// `origin`, empty if no worm starts there.
+fn worm(noise: &NoiseContext, origin: (i64, i64)) -> Vec<([f64; 3], f64)> {
+    let mut rng = noise.chunk_rng("carvers", origin);
+    if !rng.random_bool(CARVER_CHANCE) {
+        return vec![];
+    }
+    let mut point = [
+        (origin.0 * 16) as f64 + rng.random_range(0.0..16.0),
+        rng.random_range(12.0..80.0),
+        (origin.1 * 16) as f64 + rng.random_range(0.0..16.0),
+    ];
+    let mut yaw: f64 = rng.random_range(0.0..std::f64::consts::TAU);
+    let mut pitch: f64 = rng.random_range(-0.3..0.3);
+    let base_radius: f64 = rng.random_range(1.5..CARVER_MAX_BASE_RADIUS);
+
+    let mut spheres = Vec::with_capacity(CARVER_STEPS);
+    for step in 0..CARVER_STEPS {
+        // thick in the middle, thin at both ends
+        let radius = 1.0 + base_radius * (std::f64::consts::PI * step as f64 / CARVER_STEPS as f64).sin();
+        spheres.push((point, radius));
+
+        point[0] += yaw.cos() * pitch.cos();
+        point[1] += pitch.sin();
+        point[2] += yaw.sin() * pitch.cos();
+        yaw += rng.random_range(-0.25..0.25);
+        pitch = (pitch * 0.8 + rng.random_range(-0.15..0.15)).clamp(-0.8, 0.8);
+    }
+    spheres
+}
+
+fn carve_sphere(chunk: &mut Chunk, position: (i64, i64), center: [f64; 3], radius: f64) {
+    let local_x = center[0] - (position.0 * 16) as f64;
+    let local_z = center[2] - (position.1 * 16) as f64;
+    if local_x + radius < 0.0 || local_x - radius >= 16.0 || local_z + radius < 0.0 || local_z - radius >= 16.0 {
+        return;
+    }
+    let range = |center: f64, min: f64, max: f64| {
+        ((center - radius).floor().max(min) as usize)..=((center + radius).ceil().min(max) as usize)
+    };
+    for x in range(local_x, 0.0, 15.0) {
+        for z in range(local_z, 0.0, 15.0) {
+            for y in range(center[1], MIN_CAVE_HEIGHT as f64, 255.0) {
+                let distance_squared = (x as f64 + 0.5 - local_x).powi(2)
+                    + (y as f64 + 0.5 - center[1]).powi(2)
+                    + (z as f64 + 0.5 - local_z).powi(2);
+                if distance_squared < radius * radius {
+                    chunk.set_block(x, y, z, Block::AIR);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::generate_chunk;
+
+    fn stone_chunk(position: (i64, i64)) -> Chunk {
+        let mut chunk = Chunk::new((position.0 as f32, position.1 as f32));
+        for y in 0..128 {
+            for x in 0..16 {
+                for z in 0..16 {
+                    chunk.set_block(x, y, z, if y == 0 { Block::BEDROCK } else { Block::STONE });
+                }
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    fn bedrock_floor_stays() {
+        let noise = NoiseContext::new([3; 32]);
+        for position in [(0, 0), (5, -3), (-7, 2), (-20, -20)] {
+            let mut chunk = stone_chunk(position);
+            carve(&noise, &mut chunk, position, &[[120; 16]; 16]);
+            let generated = generate_chunk(&noise, position);
+            for x in 0..16 {
+                for z in 0..16 {
+                    assert_eq!(chunk.get_block(x, 0, z), Block::BEDROCK);
+                    assert_eq!(generated.get_block(x, 0, z), Block::BEDROCK);
+                    for y in 1..MIN_CAVE_HEIGHT {
+                        assert_eq!(chunk.get_block(x, y, z), Block::STONE);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn carver_range_covers_whole_worms() {
+        let noise = NoiseContext::new([3; 32]);
+        for origin_x in -20..20 {
+            for origin_z in -20..20 {
+                for (center, radius) in worm(&noise, (origin_x, origin_z)) {
+                    for (origin, center) in [(origin_x, center[0]), (origin_z, center[2])] {
+                        let reached = ((center - radius) / 16.0).floor() as i64..=((center + radius) / 16.0).floor() as i64;
+                        assert!(*reached.start() >= origin - CARVER_RANGE && *reached.end() <= origin + CARVER_RANGE);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbours_agree_on_carved_border() {
+        let noise = NoiseContext::new([3; 32]);
+        let heights = [[120; 16]; 16];
+        let mut crossing = 0;
+        for (west, east) in [((-1, 1), (0, 1)), ((4, -6), (5, -6)), ((-9, -3), (-8, -3))] {
+            // one reference carve over both chunks, from the noise caves of each and
+            // every worm from well past the carver range
+            let mut reference = [stone_chunk(west), stone_chunk(east)];
+            for (chunk, position) in reference.iter_mut().zip([west, east]) {
+                carve_noise_caves(&noise, chunk, position, &heights);
+            }
+            for origin_x in west.0 - CARVER_RANGE - 3..=east.0 + CARVER_RANGE + 3 {
+                for origin_z in west.1 - CARVER_RANGE - 3..=west.1 + CARVER_RANGE + 3 {
+                    for (center, radius) in worm(&noise, (origin_x, origin_z)) {
+                        let border = (east.0 * 16) as f64;
+                        if (center[0] - border).abs() < radius && center[2].div_euclid(16.0) == west.1 as f64 {
+                            crossing += 1;
+                        }
+                        let range = |center: f64, min: i64, max: i64| {
+                            ((center - radius).floor() as i64).max(min)..((center + radius).ceil() as i64 + 1).min(max)
+                        };
+                        for world_x in range(center[0], west.0 * 16, east.0 * 16 + 16) {
+                            for world_z in range(center[2], west.1 * 16, west.1 * 16 + 16) {
+                                for y in range(center[1], MIN_CAVE_HEIGHT as i64, 256) {
+                                    let inside = (world_x as f64 + 0.5 - center[0]).powi(2)
+                                        + (y as f64 + 0.5 - center[1]).powi(2)
+                                        + (world_z as f64 + 0.5 - center[2]).powi(2)
+                                        < radius * radius;
+                                    if inside {
+                                        let chunk = &mut reference[(world_x >= east.0 * 16) as usize];
+                                        let (x, z) = (world_x.rem_euclid(16) as usize, world_z.rem_euclid(16) as usize);
+                                        chunk.set_block(x, y as usize, z, Block::AIR);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            for (reference, position) in reference.iter().zip([west, east]) {
+                let mut chunk = stone_chunk(position);
+                carve(&noise, &mut chunk, position, &heights);
+                assert_eq!(chunk.to_bytes(), reference.to_bytes(), "chunk {:?}", position);
+            }
+        }
+        assert!(crossing > 0, "no worm crosses a tested border");
+    }
+}
@@ -0,0 +1,225 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::*;
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{biome::Biome, block::Block, generation::NoiseContext, world::Chunk};
+
+pub const PENDING_FILE_NAME: &str = "pending_placements.bin";
+
+/// A block placed by a decoration, in chunk coordinates plus chunk-local coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub chunk: (i64, i64),
+    pub x: usize,
+    pub y: usize,
+    pub z: usize,
+    pub block: Block,
+}
+
+impl Placement {
+    /// Placement at a block position relative to the origin of `chunk`, the position
+    /// may lie in a neighbouring chunk.
+    fn new(chunk: (i64, i64), x: i64, y: i64, z: i64, block: Block) -> Option<Self> {
+        if !(1..256).contains(&y) {
+            return None;
+        }
+        let world_x = chunk.0 * 16 + x;
+        let world_z = chunk.1 * 16 + z;
+        Some(Self {
+            chunk: (world_x.div_euclid(16), world_z.div_euclid(16)),
+            x: world_x.rem_euclid(16) as usize,
+            y: y as usize,
+            z: world_z.rem_euclid(16) as usize,
+            block,
+        })
+    }
+
+    /// Applies the placement if it outranks the block already there, returns whether
+    /// the chunk changed.
+    pub fn apply(&self, chunk: &mut Chunk) -> bool {
+        let existing = chunk.get_block(self.x, self.y, self.z);
+        match (placement_rank(existing), placement_rank(self.block)) {
+            (Some(existing_rank), Some(rank)) if rank > existing_rank => {
+                chunk.set_block(self.x, self.y, self.z, self.block);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Decorations may only overwrite air and decoration blocks of a lower rank, never
+/// terrain. Because the highest rank always wins, the result is the same whichever
+/// order overlapping decorations are applied in.
+fn placement_rank(block: Block) -> Option<u8> {
+    match block {
+        Block::AIR => Some(0),
+        Block::LEAVES => Some(1),
+        Block::LOG => Some(2),
+        Block::COBBLESTONE => Some(3),
+        _ => None,
+    }
+}
+
+/// Decorates a freshly generated chunk with trees, boulders and ruins. `chunk` must not
+/// contain placements from neighbouring chunks yet, so the surface is found on the
+/// bare terrain. Returned placements can fall into neighbouring chunks.
+pub fn decorate(noise: &NoiseContext, chunk: &Chunk, position: (i64, i64)) -> Vec<Placement> {
+    let mut rng = noise.chunk_rng("decoration", position);
+    let mut placements = vec![];
+
+    let biome = chunk.biomes.ids[8][8];
+    let (trees, boulder_chance) = match biome {
+        Biome::Forest => (6, 0.05),
+        Biome::Plains => (1, 0.1),
+        Biome::Tundra => (1, 0.05),
+        Biome::Mountains => (0, 0.4),
+        Biome::Desert | Biome::Ocean => (0, 0.0),
+    };
+
+    for _ in 0..trees {
+        let (x, z) = (rng.random_range(0..16), rng.random_range(0..16));
+        if let Some(y) = surface(chunk, x, z, &[Block::GRASS, Block::DIRT, Block::SNOW]) {
+            tree(&mut rng, position, (x as i64, y as i64 + 1, z as i64), &mut placements);
+        }
+    }
+    if rng.random_bool(boulder_chance) {
+        let (x, z) = (rng.random_range(0..16), rng.random_range(0..16));
+        if let Some(y) = surface(chunk, x, z, &[Block::GRASS, Block::DIRT, Block::STONE, Block::SNOW]) {
+            boulder(&mut rng, position, (x as i64, y as i64 + 1, z as i64), &mut placements);
+        }
+    }
+    if rng.random_bool(RUIN_CHANCE) {
+        let (x, z) = (rng.random_range(0..16), rng.random_range(0..16));
+        if let Some(y) = surface(chunk, x, z, &[Block::GRASS, Block::DIRT, Block::SAND, Block::SNOW]) {
+            ruin(&mut rng, position, (x as i64, y as i64 + 1, z as i64), &mut placements);
+        }
+    }
+    placements
+}
+
+const RUIN_CHANCE: f64 = 0.01;
+
+/// Height of the highest block of a column if it is one of `allowed`.
+fn surface(chunk: &Chunk, x: usize, z: usize, allowed: &[Block]) -> Option<usize> {
+    let y = (0..255).rev().find(|y| chunk.get_block(x, *y, z) != Block::AIR)?;
+    allowed.contains(&chunk.get_block(x, y, z)).then_some(y)
+}
+
+fn tree(rng: &mut ChaCha8Rng, chunk: (i64, i64), base: (i64, i64, i64), placements: &mut Vec<Placement>) {
+    let height = rng.random_range(4..=6);
+    let (x, y, z) = base;
+    let top = y + height;
+    for dy in -3..=1 {
+        let radius: i64 = if dy >= 0 { 1 } else { 2 };
+        for dx in -radius..=radius {
+            for dz in -radius..=radius {
+                // round off the corners of the canopy
+                if dx.abs() == radius && dz.abs() == radius && (dy >= 0 || rng.random_bool(0.5)) {
+                    continue;
+                }
+                placements.extend(Placement::new(chunk, x + dx, top + dy, z + dz, Block::LEAVES));
+            }
+        }
+    }
+    for dy in 0..height {
+        placements.extend(Placement::new(chunk, x, y + dy, z, Block::LOG));
+    }
+}
+
+fn boulder(rng: &mut ChaCha8Rng, chunk: (i64, i64), center: (i64, i64, i64), placements: &mut Vec<Placement>) {
+    let radius: f64 = rng.random_range(1.0..2.5);
+    let extent = radius.ceil() as i64;
+    for dx in -extent..=extent {
+        for dy in -extent..=extent {
+            for dz in -extent..=extent {
+                if ((dx * dx + dy * dy + dz * dz) as f64) < radius * radius {
+                    let (x, y, z) = center;
+                    placements.extend(Placement::new(chunk, x + dx, y + dy, z + dz, Block::COBBLESTONE));
+                }
+            }
+        }
+    }
+}
+
+/// A crumbling square of cobblestone walls.
+fn ruin(rng: &mut ChaCha8Rng, chunk: (i64, i64), corner: (i64, i64, i64), placements: &mut Vec<Placement>) {
+    let size = rng.random_range(5..=7);
+    let (x, y, z) = corner;
+    for dx in 0..size {
+        for dz in 0..size {
+            placements.extend(Placement::new(chunk, x + dx, y, z + dz, Block::COBBLESTONE));
+            let is_wall = dx == 0 || dz == 0 || dx == size - 1 || dz == size - 1;
+            if !is_wall {
+                continue;
+            }
+            let wall_height = rng.random_range(0..=3);
+            for dy in 1..=wall_height {
+                placements.extend(Placement::new(chunk, x + dx, y + dy, z + dz, Block::COBBLESTONE));
+            }
+        }
+    }
+}
+
+/// Placements waiting for their chunk to be generated or loaded.
+#[derive(Debug, Default)]
+pub struct PendingPlacements {
+    chunks: HashMap<(i64, i64), Vec<Placement>>,
+}
+
+impl PendingPlacements {
+    pub fn push(&mut self, placement: Placement) {
+        self.chunks.entry(placement.chunk).or_default().push(placement);
+    }
+
+    pub fn take(&mut self, chunk: (i64, i64)) -> Vec<Placement> {
+        self.chunks.remove(&chunk).unwrap_or_default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(Vec::len).sum()
+    }
+
+    /// Reads the queue saved by [`PendingPlacements::save`], entries are
+    /// `chunk x: i64, chunk z: i64, x: u8, y: u8, z: u8, block: u8`, little endian.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(PENDING_FILE_NAME);
+        let mut pending = Self::default();
+        if !path.exists() {
+            return Ok(pending);
+        }
+        let bytes = fs::read(&path).with_context(|| format!("reading {:?}", path))?;
+        for entry in bytes.chunks(PENDING_ENTRY_SIZE) {
+            if entry.len() != PENDING_ENTRY_SIZE {
+                bail!("{:?} is truncated", path);
+            }
+            pending.push(Placement {
+                chunk: (
+                    i64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                    i64::from_le_bytes(entry[8..16].try_into().unwrap()),
+                ),
+                x: entry[16] as usize,
+                y: entry[17] as usize,
+                z: entry[18] as usize,
+                block: Block(entry[19]),
+            });
+        }
+        Ok(pending)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let mut bytes = Vec::with_capacity(self.len() * PENDING_ENTRY_SIZE);
+        for placement in self.chunks.values().flatten() {
+            bytes.extend_from_slice(&placement.chunk.0.to_le_bytes());
+            bytes.extend_from_slice(&placement.chunk.1.to_le_bytes());
+            bytes.extend_from_slice(&[placement.x as u8, placement.y as u8, placement.z as u8, placement.block.0]);
+        }
+        let path = dir.join(PENDING_FILE_NAME);
+        fs::write(&path, bytes).with_context(|| format!("writing {:?}", path))?;
+        Ok(())
+    }
+}
+
+const PENDING_ENTRY_SIZE: usize = 20;
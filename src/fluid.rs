@@ -0,0 +1,256 @@
+use serde::Deserialize;
+
+use crate::{
+    block::{Block, BlockRegistry},
+    world::World,
+    Cardinal,
+};
+
+/// Level of a fluid filling its whole block. Sources and falling fluid are full.
+pub const FULL: u8 = 8;
+
+/// Highest block filled with water by terrain generation, wherever the terrain is
+/// lower.
+pub const SEA_LEVEL: usize = 34;
+
+const HORIZONTAL: [Cardinal; 4] = [Cardinal::North, Cardinal::South, Cardinal::East, Cardinal::West];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FluidKind {
+    Water,
+    Lava,
+}
+
+impl FluidKind {
+    /// Ticks the fluid waits before each step it flows.
+    pub fn delay(self) -> u64 {
+        match self {
+            FluidKind::Water => 10,
+            FluidKind::Lava => 30,
+        }
+    }
+
+    /// Levels the fluid loses with every block it flows sideways, so water reaches 7
+    /// blocks from its source and lava 3.
+    pub fn decay(self) -> u8 {
+        match self {
+            FluidKind::Water => 1,
+            FluidKind::Lava => 2,
+        }
+    }
+}
+
+/// The fluid in a block, given by the `fluid` key of its registry entry. Every level
+/// of a fluid is its own block id, so levels are saved and meshed like any other
+/// block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fluid {
+    pub kind: FluidKind,
+    /// how much of the block the fluid fills in eighths, from 1 to `FULL`
+    pub level: u8,
+    /// sources keep their level, flowing fluid drains away once nothing feeds it
+    #[serde(default)]
+    pub source: bool,
+}
+
+impl Fluid {
+    pub fn source(kind: FluidKind) -> Self {
+        Self { kind, level: FULL, source: true }
+    }
+
+    pub fn flowing(kind: FluidKind, level: u8) -> Self {
+        Self { kind, level, source: false }
+    }
+}
+
+impl World {
+    /// Reacts to a change next to a fluid. Lava touched by water turns into
+    /// cobblestone right away, anything else flows after the fluid's delay.
+    pub fn fluid_neighbour_changed(&mut self, registry: &BlockRegistry, position: [i64; 3], fluid: Fluid) {
+        if fluid.kind == FluidKind::Lava && self.touches_water(registry, position) {
+            self.set_block(position, Block::COBBLESTONE);
+            return;
+        }
+        self.updates.schedule(position, fluid.kind.delay());
+    }
+
+    /// One step of a fluid flowing. Flowing fluid first takes the level its
+    /// neighbours feed it, then fluid flows down if it can, and sideways one level
+    /// lower if it can not or is a source. Every block changed notifies its
+    /// neighbours, which keeps the fluid flowing on the following steps.
+    pub fn flow(&mut self, registry: &BlockRegistry, position: [i64; 3], fluid: Fluid) {
+        if !fluid.source {
+            let fed = self.fed_fluid(registry, position, fluid.kind);
+            if fed != Some(fluid) {
+                let block = fed.and_then(|fed| registry.fluid_block(fed)).unwrap_or(Block::AIR);
+                self.set_block(position, block);
+                return;
+            }
+        }
+
+        // flowing fluid above an opening or more of itself only goes down
+        let below = offset(position, Cardinal::Down);
+        let falls = self.flow_into(registry, below, Fluid::flowing(fluid.kind, FULL))
+            || self.fluid_at(registry, below, fluid.kind).is_some();
+        if falls && !fluid.source {
+            return;
+        }
+        let Some(level) = fluid.level.checked_sub(fluid.kind.decay()).filter(|level| *level > 0) else {
+            return;
+        };
+        for face in HORIZONTAL {
+            self.flow_into(registry, offset(position, face), Fluid::flowing(fluid.kind, level));
+        }
+    }
+
+    /// The fluid a block that is not a source would hold, from what flows into it.
+    /// Fluid from above fills it, otherwise it is one step lower than its highest
+    /// neighbour of the same kind. Water between two sources on top of something
+    /// that holds it becomes a source itself.
+    fn fed_fluid(&self, registry: &BlockRegistry, position: [i64; 3], kind: FluidKind) -> Option<Fluid> {
+        let fluid_at = |face| self.fluid_at(registry, offset(position, face), kind);
+        let neighbours = HORIZONTAL.map(fluid_at);
+        let sources = neighbours.iter().flatten().filter(|fluid| fluid.source).count();
+        if kind == FluidKind::Water && sources >= 2 {
+            let holds = self.loaded_block(offset(position, Cardinal::Down)).is_some_and(|below| {
+                registry.get(below).solid || registry.get(below).fluid == Some(Fluid::source(kind))
+            });
+            if holds {
+                return Some(Fluid::source(kind));
+            }
+        }
+        if fluid_at(Cardinal::Up).is_some() {
+            return Some(Fluid::flowing(kind, FULL));
+        }
+        let level = neighbours.iter().flatten().map(|fluid| fluid.level).max()?;
+        level.checked_sub(kind.decay()).filter(|level| *level > 0).map(|level| Fluid::flowing(kind, level))
+    }
+
+    /// The fluid at `position` if it is of `kind`.
+    fn fluid_at(&self, registry: &BlockRegistry, position: [i64; 3], kind: FluidKind) -> Option<Fluid> {
+        let block = self.loaded_block(position)?;
+        registry.get(block).fluid.filter(|fluid| fluid.kind == kind)
+    }
+
+    /// Lets `fluid` flow into the block at `position` if it can, and returns whether
+    /// it did. Fluid replaces blocks that are not solid and lower flowing fluid of its
+    /// kind. Where lava and water meet the block turns into cobblestone.
+    fn flow_into(&mut self, registry: &BlockRegistry, position: [i64; 3], fluid: Fluid) -> bool {
+        if !(0..256).contains(&position[1]) {
+            return false;
+        }
+        let Some(block) = self.loaded_block(position) else {
+            return false;
+        };
+        let definition = registry.get(block);
+        let replacement = match definition.fluid {
+            Some(other) if other.kind != fluid.kind => Some(Block::COBBLESTONE),
+            Some(other) if other.source || other.level >= fluid.level => None,
+            _ if definition.solid => None,
+            _ => registry.fluid_block(fluid),
+        };
+        match replacement {
+            Some(replacement) => self.set_block(position, replacement).is_some(),
+            None => false,
+        }
+    }
+
+    /// Whether water is next to the block, on any side but below.
+    fn touches_water(&self, registry: &BlockRegistry, position: [i64; 3]) -> bool {
+        Cardinal::ALL
+            .into_iter()
+            .filter(|face| *face != Cardinal::Down)
+            .any(|face| self.fluid_at(registry, offset(position, face), FluidKind::Water).is_some())
+    }
+}
+
+fn offset(position: [i64; 3], face: Cardinal) -> [i64; 3] {
+    let [dx, dy, dz] = face.normal();
+    [position[0] + dx, position[1] + dy, position[2] + dz]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_util::{floor_world, registry},
+        time::Time,
+    };
+
+    /// Runs block updates until nothing is left to do.
+    fn settle(world: &mut World, registry: &BlockRegistry, time: &mut Time) {
+        for _ in 0..5000 {
+            if world.updates.is_empty() {
+                return;
+            }
+            world.tick_blocks(registry, time.tick);
+            time.finish_tick();
+        }
+        panic!("fluids are still flowing");
+    }
+
+    /// The fluid on the floor at a few blocks `distance` steps from x 8, z 8, along each
+    /// horizontal axis and one off the axis.
+    fn fluids_at(world: &World, registry: &BlockRegistry, distance: i64) -> Vec<Option<Fluid>> {
+        [[distance, 0], [-distance, 0], [0, distance], [0, -distance], [distance - 1, 1]]
+            .iter()
+            .map(|[dx, dz]| registry.get(world.loaded_block([8 + dx, 11, 8 + dz]).unwrap()).fluid)
+            .collect()
+    }
+
+    fn spreads(kind: FluidKind, reach: i64) {
+        let registry = registry();
+        let mut world = floor_world(1);
+        let mut time = Time::new();
+        world.set_block([8, 11, 8], registry.fluid_block(Fluid::source(kind)).unwrap());
+        settle(&mut world, &registry, &mut time);
+
+        for distance in 1..=reach {
+            let level = FULL - distance as u8 * kind.decay();
+            let expected = Some(Fluid::flowing(kind, level));
+            assert!(fluids_at(&world, &registry, distance).iter().all(|fluid| *fluid == expected), "{} blocks away", distance);
+        }
+        assert!(fluids_at(&world, &registry, reach + 1).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn water_spreads_seven_blocks() {
+        spreads(FluidKind::Water, 7);
+    }
+
+    #[test]
+    fn lava_spreads_three_blocks() {
+        spreads(FluidKind::Lava, 3);
+    }
+
+    #[test]
+    fn flowing_water_drains_without_its_source() {
+        let registry = registry();
+        let mut world = floor_world(1);
+        let mut time = Time::new();
+        world.set_block([8, 11, 8], Block::WATER);
+        settle(&mut world, &registry, &mut time);
+        assert!(fluids_at(&world, &registry, 7).iter().all(Option::is_some));
+
+        world.set_block([8, 11, 8], Block::AIR);
+        settle(&mut world, &registry, &mut time);
+        for x in -8..24 {
+            for z in -8..24 {
+                assert_eq!(world.loaded_block([x, 11, z]), Some(Block::AIR), "at x {} z {}", x, z);
+            }
+        }
+    }
+
+    #[test]
+    fn lava_next_to_water_turns_to_cobblestone() {
+        let registry = registry();
+        let mut world = floor_world(1);
+        world.set_block([8, 11, 8], Block::LAVA);
+        world.set_block([9, 11, 8], Block::WATER);
+        world.tick_blocks(&registry, 0);
+        assert_eq!(world.loaded_block([8, 11, 8]), Some(Block::COBBLESTONE));
+        assert_eq!(world.loaded_block([9, 11, 8]), Some(Block::WATER));
+    }
+}
@@ -0,0 +1,134 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+/// Axis aligned box in render coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    /// Whether the boxes overlap. Boxes that only touch do not.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x < other.max.x
+            && other.min.x < self.max.x
+            && self.min.y < other.max.y
+            && other.min.y < self.max.y
+            && self.min.z < other.max.z
+            && other.min.z < self.max.z
+    }
+}
+
+/// The six planes bounding what a view projection matrix puts on screen, with normals
+/// pointing inwards. A point `p` is inside a plane when `dot(plane.xyz, p) + plane.w`
+/// is not negative.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a combined projection and view matrix, such as
+    /// `Projection::calc_matrix() * Camera::calc_matrix()`. Clip space depth is
+    /// expected to go from 0 to 1 as in wgpu.
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|row| view_proj.row(row));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            // normalised so distances to the planes are in world units
+            plane / plane.truncate().magnitude()
+        });
+        Self { planes }
+    }
+
+    /// Whether any part of the box can be inside the frustum. Boxes near the corners
+    /// of the frustum may be kept even though they are just outside.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use cgmath::Deg;
+
+    /// Frustum of a camera at the origin, 40 degrees high and 4:3 wide. Every side is
+    /// well within 45 degrees of the view direction.
+    fn frustum(yaw: f32, pitch: f32) -> Frustum {
+        let projection = Projection::new(800, 600, Deg(40.0), 0.1, 100.0);
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(yaw), Deg(pitch));
+        Frustum::from_matrix(projection.calc_matrix() * camera.calc_matrix())
+    }
+
+    fn unit_box(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::new(Vector3::new(x - 0.5, y - 0.5, z - 0.5), Vector3::new(x + 0.5, y + 0.5, z + 0.5))
+    }
+
+    #[test]
+    fn box_in_front_is_visible() {
+        // yaw 0 looks along +x
+        let frustum = frustum(0.0, 0.0);
+        assert!(frustum.intersects(&unit_box(20.0, 0.0, 0.0)));
+        assert!(frustum.intersects(&unit_box(2.0, 0.0, 0.0)));
+        assert!(frustum.intersects(&unit_box(20.0, 4.0, 6.0)));
+    }
+
+    #[test]
+    fn box_behind_is_culled() {
+        let frustum = frustum(0.0, 0.0);
+        assert!(!frustum.intersects(&unit_box(-20.0, 0.0, 0.0)));
+        assert!(!frustum.intersects(&unit_box(-2.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn boxes_outside_each_side_are_culled() {
+        let frustum = frustum(0.0, 0.0);
+        // right is +z
+        for (name, center) in [
+            ("left", [20.0, 0.0, -20.0]),
+            ("right", [20.0, 0.0, 20.0]),
+            ("bottom", [20.0, -20.0, 0.0]),
+            ("top", [20.0, 20.0, 0.0]),
+        ] {
+            let [x, y, z] = center;
+            assert!(!frustum.intersects(&unit_box(x, y, z)), "{} plane", name);
+        }
+    }
+
+    #[test]
+    fn boxes_across_a_side_are_visible() {
+        let frustum = frustum(0.0, 0.0);
+        let across = Aabb::new(Vector3::new(19.0, -1.0, 5.0), Vector3::new(21.0, 1.0, 25.0));
+        assert!(frustum.intersects(&across));
+        let around = Aabb::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+        assert!(frustum.intersects(&around));
+    }
+
+    #[test]
+    fn planes_turn_with_the_camera() {
+        // yaw 90 looks along +z, pitch 90 nearly straight up
+        let turned = frustum(90.0, 0.0);
+        assert!(turned.intersects(&unit_box(0.0, 0.0, 20.0)));
+        assert!(!turned.intersects(&unit_box(20.0, 0.0, 0.0)));
+        let up = frustum(0.0, 89.0);
+        assert!(up.intersects(&unit_box(0.0, 20.0, 0.0)));
+        assert!(!up.intersects(&unit_box(20.0, 0.0, 0.0)));
+    }
+}
@@ -0,0 +1,291 @@
+use std::hash::{Hash, Hasher};
+
+use noise::{NoiseFn, Perlin};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rand_seeder::SipHasher;
+
+use crate::{
+    biome::BiomeBlend,
+    block::Block,
+    caves,
+    decoration::{self, Placement},
+    fluid::SEA_LEVEL,
+    ore,
+    world::Chunk,
+};
+
+/// Amplitude and frequency of each terrain height octave, from fine detail to
+/// continent scale.
+const HEIGHT_OCTAVES: [(f64, f64); 4] = [(10.0, 3.0e-1), (50.0, 5.0e-2), (75.0, 5.0e-3), (100.0, 5.0e-7)];
+
+/// How far above and below the height map the 3D density noise reshapes terrain.
+const OVERHANG_RANGE: usize = 12;
+const OVERHANG_FREQUENCY: f64 = 3.0e-2;
+/// Weight of the density noise against the height map, values above one allow
+/// floating terrain near the surface.
+const OVERHANG_STRENGTH: f64 = 0.9;
+/// Number of filler blocks under the surface block.
+const FILLER_DEPTH: usize = 4;
+
+/// Seeded noise generators for every world generation layer.
+///
+/// Each layer gets its own sub-seed derived from the full 32 byte world seed and the
+/// layer name, so layers are independent of each other and the same seed always
+/// produces the same world.
+pub struct NoiseContext {
+    seed: [u8; 32],
+    pub height: [Perlin; 4],
+    pub density: Perlin,
+    pub caves: [Perlin; 3],
+    pub temperature: Perlin,
+    pub humidity: Perlin,
+    pub ores: Perlin,
+}
+
+impl NoiseContext {
+    pub fn new(seed: [u8; 32]) -> Self {
+        let perlin = |layer: &str, index: u32| Perlin::new(sub_seed(&seed, layer, index) as u32);
+        Self {
+            seed,
+            height: std::array::from_fn(|i| perlin("height", i as u32)),
+            density: perlin("density", 0),
+            caves: std::array::from_fn(|i| perlin("caves", i as u32)),
+            temperature: perlin("temperature", 0),
+            humidity: perlin("humidity", 0),
+            ores: perlin("ores", 0),
+        }
+    }
+
+    pub fn seed(&self) -> &[u8; 32] {
+        &self.seed
+    }
+
+    /// Random number generator for a layer in a single chunk. It only depends on the
+    /// world seed, the layer and the chunk position, never on generation order.
+    pub fn chunk_rng(&self, layer: &str, chunk: (i64, i64)) -> ChaCha8Rng {
+        let mut hasher = SipHasher::from((&self.seed, layer));
+        chunk.hash(&mut hasher);
+        ChaCha8Rng::from_rng(&mut hasher.into_rng())
+    }
+
+    /// Terrain height noise of a column in -1..1, `x` and `z` are in chunk units.
+    pub fn height_noise(&self, x: f64, z: f64) -> f64 {
+        let y: f64 = self
+            .height
+            .iter()
+            .zip(HEIGHT_OCTAVES)
+            .map(|(perlin, (amplitude, frequency))| amplitude * perlin.get([frequency * x, frequency * z]))
+            .sum();
+        let total_amplitude: f64 = HEIGHT_OCTAVES.iter().map(|(amplitude, _)| amplitude).sum();
+        y / total_amplitude
+    }
+}
+
+/// Sub-seed of one noise layer, `index` tells apart several generators of a layer.
+fn sub_seed(seed: &[u8; 32], layer: &str, index: u32) -> u64 {
+    let mut hasher = SipHasher::from((seed, layer));
+    index.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Generates the terrain of the chunk at `position`, in chunk coordinates.
+pub fn generate_chunk(noise: &NoiseContext, position: (i64, i64)) -> Chunk {
+    let mut chunk = Chunk::new((position.0 as f32, position.1 as f32));
+    let mut heights = [[0; 16]; 16];
+    for (x, column) in heights.iter_mut().enumerate() {
+        for (z, top) in column.iter_mut().enumerate() {
+            let (column_x, column_z) = (x as f64 / 16.0 + position.0 as f64, z as f64 / 16.0 + position.1 as f64);
+            let blend = BiomeBlend::at(noise, column_x, column_z);
+            let biome = blend.dominant();
+            chunk.biomes.ids[x][z] = biome;
+            chunk.biomes.tints[x][z] = blend.tint();
+
+            let height = blend.height(noise.height_noise(column_x, column_z));
+            *top = (height + OVERHANG_RANGE).min(255);
+
+            // walk down the column, the first solid block after air is the surface
+            // and the next few are filler
+            let world_x = (position.0 * 16 + x as i64) as f64;
+            let world_z = (position.1 * 16 + z as i64) as f64;
+            let mut depth = None;
+            for y in (1..=*top).rev() {
+                if !is_solid(noise, height, [world_x, y as f64, world_z]) {
+                    depth = None;
+                    continue;
+                }
+                let block_depth = depth.map_or(0, |depth| depth + 1);
+                depth = Some(block_depth);
+                let block = match block_depth {
+                    0 => biome.parameters().surface,
+                    1..=FILLER_DEPTH if y > FILLER_DEPTH => biome.parameters().filler,
+                    _ => Block::STONE,
+                };
+                chunk.set_block(x, y, z, block);
+            }
+        }
+    }
+    caves::carve(noise, &mut chunk, position, &heights);
+    ore::place_ores(noise, &mut chunk, position, &ore::DEFAULT_ORES);
+    flood(&mut chunk, &heights);
+    for x in 0..16 {
+        for z in 0..16 {
+            chunk.set_block(x, 0, z, Block::BEDROCK);
+        }
+    }
+    chunk
+}
+
+/// Generates the chunk at `position` along with its decorations, which can fall into
+/// neighbouring chunks. Decorations are planned on the bare terrain, before
+/// neighbours add theirs.
+pub fn generate_decorated(noise: &NoiseContext, position: (i64, i64)) -> (Chunk, Vec<Placement>) {
+    let chunk = generate_chunk(noise, position);
+    let placements = decoration::decorate(noise, &chunk, position);
+    (chunk, placements)
+}
+
+/// Fills the air open to the sky up to `SEA_LEVEL` with still water, walking down
+/// each column from `heights`, above which there is only air. Caves below the first
+/// block stay dry. Grass does not grow under water, so a flooded grass surface turns
+/// into dirt.
+fn flood(chunk: &mut Chunk, heights: &[[usize; 16]; 16]) {
+    for (x, column) in heights.iter().enumerate() {
+        for (z, &top) in column.iter().enumerate() {
+            for y in (1..=top.max(SEA_LEVEL)).rev() {
+                match chunk.get_block(x, y, z) {
+                    Block::AIR if y <= SEA_LEVEL => chunk.set_block(x, y, z, Block::WATER),
+                    Block::AIR => {}
+                    Block::GRASS if y <= SEA_LEVEL => {
+                        chunk.set_block(x, y, z, Block::DIRT);
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+}
+
+/// Density function of the terrain: solid below the height map, plus 3D noise within
+/// `OVERHANG_RANGE` of the surface that carves out cliffs, overhangs and arches.
+fn is_solid(noise: &NoiseContext, height: usize, point: [f64; 3]) -> bool {
+    let offset = height as f64 - point[1];
+    if offset > OVERHANG_RANGE as f64 {
+        return true;
+    }
+    if offset < -(OVERHANG_RANGE as f64) {
+        return false;
+    }
+    let density = offset / OVERHANG_RANGE as f64
+        + OVERHANG_STRENGTH * noise.density.get(point.map(|x| x * OVERHANG_FREQUENCY));
+    density > 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::World;
+
+    fn chunk_bytes(seed: &str, position: (i64, i64)) -> Vec<u8> {
+        let noise = World::new(seed.to_string(), 2).noise();
+        generate_chunk(&noise, position).to_bytes()
+    }
+
+    /// Hash of the blocks of a chunk, stable across builds and platforms.
+    fn block_hash(seed: &str, position: (i64, i64)) -> u64 {
+        let mut hasher = SipHasher::new();
+        hasher.write(&chunk_bytes(seed, position));
+        hasher.finish()
+    }
+
+    // Saved worlds only store modified chunks and regenerate the rest, so changing
+    // what a seed generates changes existing worlds. Update these values only when
+    // that is intended.
+    #[test]
+    fn golden_chunks() {
+        for (position, hash) in [
+            ((0, 0), 0x0b22_497f_b5fb_d744),
+            ((-3, 7), 0xfff9_319c_03aa_4617),
+            ((120, -45), 0x50c2_fd85_a2a9_8890),
+        ] {
+            assert_eq!(block_hash("golden", position), hash, "chunk {:?}", position);
+        }
+    }
+
+    #[test]
+    fn golden_surface() {
+        let noise = World::new("golden".to_string(), 2).noise();
+        for (position, column, top, block) in [
+            ((0, 0), (0, 0), 54, Block::GRASS),
+            ((0, 0), (7, 9), 55, Block::GRASS),
+            ((-3, 7), (15, 3), 44, Block::GRASS),
+            ((120, -45), (0, 0), 66, Block::GRASS),
+            ((120, -45), (7, 9), 46, Block::STONE),
+        ] {
+            let chunk = generate_chunk(&noise, position);
+            let (x, z) = column;
+            let surface = (0..256).rev().find(|&y| chunk.get_block(x, y, z) != Block::AIR);
+            assert_eq!(surface, Some(top), "chunk {:?} column {:?}", position, column);
+            assert_eq!(chunk.get_block(x, top, z), block, "chunk {:?} column {:?}", position, column);
+            assert_eq!(chunk.get_block(x, 0, z), Block::BEDROCK);
+        }
+    }
+
+    #[test]
+    fn different_seeds_different_chunks() {
+        for position in [(0, 0), (-3, 7), (120, -45)] {
+            assert_ne!(chunk_bytes("golden", position), chunk_bytes("golden!", position), "chunk {:?}", position);
+        }
+    }
+
+    #[test]
+    fn buried_caves_stay_dry() {
+        // stone up to y 60 with a pit open to the sky at x 2 and a cave at sea level
+        // under solid rock at x 8
+        let mut chunk = Chunk::new((0.0, 0.0));
+        for x in 0..16 {
+            for z in 0..16 {
+                for y in 1..=60 {
+                    chunk.set_block(x, y, z, Block::STONE);
+                }
+            }
+        }
+        for y in 21..=60 {
+            chunk.set_block(2, y, 2, Block::AIR);
+        }
+        for y in SEA_LEVEL - 3..=SEA_LEVEL + 2 {
+            for z in 6..10 {
+                chunk.set_block(8, y, z, Block::AIR);
+            }
+        }
+        flood(&mut chunk, &[[60; 16]; 16]);
+
+        for y in 21..=SEA_LEVEL {
+            assert_eq!(chunk.get_block(2, y, 2), Block::WATER, "pit at y {}", y);
+        }
+        assert_eq!(chunk.get_block(2, SEA_LEVEL + 1, 2), Block::AIR);
+        assert_eq!(chunk.get_block(2, 20, 2), Block::STONE);
+        for y in SEA_LEVEL - 3..=SEA_LEVEL + 2 {
+            assert_eq!(chunk.get_block(8, y, 7), Block::AIR, "cave at y {}", y);
+        }
+    }
+
+    #[test]
+    fn low_ground_is_flooded_to_sea_level() {
+        let mut chunk = Chunk::new((0.0, 0.0));
+        for x in 0..16 {
+            for z in 0..16 {
+                for y in 1..20 {
+                    chunk.set_block(x, y, z, Block::STONE);
+                }
+                chunk.set_block(x, 20, z, Block::GRASS);
+            }
+        }
+        flood(&mut chunk, &[[20; 16]; 16]);
+        assert_eq!(chunk.get_block(4, 20, 9), Block::DIRT);
+        assert_eq!(chunk.get_block(4, 21, 9), Block::WATER);
+        assert_eq!(chunk.get_block(4, SEA_LEVEL, 9), Block::WATER);
+        assert_eq!(chunk.get_block(4, SEA_LEVEL + 1, 9), Block::AIR);
+    }
+}
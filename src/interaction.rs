@@ -0,0 +1,51 @@
+use crate::{
+    block::{Block, BlockRegistry},
+    camera::Camera,
+    player,
+    world::{self, RayHit, World},
+};
+
+/// How far from the camera blocks can be broken and placed, in blocks.
+pub const REACH: f32 = 6.0;
+
+/// Blocks the number keys select, by name in the block registry.
+pub const HOTBAR: [&str; 9] = ["grass", "dirt", "stone", "cobblestone", "sand", "gravel", "glass", "water", "lava"];
+
+/// The block the camera looks at. Blocks that are not solid, like water, are looked
+/// through.
+pub fn target(world: &World, registry: &BlockRegistry, camera: &Camera) -> Option<RayHit> {
+    world.raycast(camera.position, camera.forward(), REACH, |block| registry.get(block).solid)
+}
+
+/// Breaks the block the camera looks at and returns it. Unbreakable blocks, those with
+/// a negative hardness like bedrock, stay.
+pub fn break_block(world: &mut World, registry: &BlockRegistry, camera: &Camera) -> Option<Block> {
+    let hit = target(world, registry, camera)?;
+    let block = world.loaded_block(hit.block)?;
+    if registry.get(block).hardness < 0.0 {
+        log::info!("{} at {:?} can not be broken", registry.get(block).name, hit.block);
+        return None;
+    }
+    world.set_block(hit.block, Block::AIR)
+}
+
+/// Places `block` against the face the camera looks at. Only blocks that are not
+/// solid, like air and water, are replaced, and nothing is placed where it would
+/// overlap the player.
+pub fn place_block(world: &mut World, registry: &BlockRegistry, camera: &Camera, block: Block) -> bool {
+    let Some(hit) = target(world, registry, camera) else {
+        return false;
+    };
+    let position = hit.adjacent();
+    let Some(replaced) = world.loaded_block(position) else {
+        return false;
+    };
+    if !(0..256).contains(&position[1]) || registry.get(replaced).solid {
+        return false;
+    }
+    if registry.get(block).solid && world::block_bounds(position).intersects(&player::player_bounds(camera.position)) {
+        log::info!("not placing {} at {:?} inside the player", registry.get(block).name, position);
+        return false;
+    }
+    world.set_block(position, block).is_some()
+}
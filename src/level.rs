@@ -0,0 +1,111 @@
+use std::{fs, path::Path};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+
+/// Version of the level descriptor written by this build. Bump it together with a new
+/// step in [`migrate`] whenever the layout of [`Level`] changes.
+pub const LEVEL_FORMAT_VERSION: u32 = 1;
+pub const LEVEL_FILE_NAME: &str = "level.toml";
+
+/// Camera position in render space, yaw and pitch in radians.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraPose {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl CameraPose {
+    pub fn to_camera(self) -> Camera {
+        Camera::new(self.position, cgmath::Rad(self.yaw), cgmath::Rad(self.pitch))
+    }
+}
+
+impl From<&Camera> for CameraPose {
+    fn from(camera: &Camera) -> Self {
+        Self {
+            position: camera.position.into(),
+            yaw: camera.yaw().0,
+            pitch: camera.pitch().0,
+        }
+    }
+}
+
+/// World level descriptor, stored next to the region files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    pub version: u32,
+    pub seed_string: String,
+    #[serde(with = "seed_hex")]
+    pub seed: [u8; 32],
+    pub spawn: [f32; 3],
+    pub camera: CameraPose,
+    pub render_distance: u32,
+}
+
+impl Level {
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(LEVEL_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path).with_context(|| format!("reading {:?}", path))?;
+        let table: toml::Table = toml::from_str(&text).with_context(|| format!("parsing {:?}", path))?;
+        let level = migrate(table)?
+            .try_into()
+            .with_context(|| format!("reading level data from {:?}", path))?;
+        Ok(Some(level))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(LEVEL_FILE_NAME);
+        fs::create_dir_all(dir)?;
+        // write to a temporary file first so a crash never leaves a half written level
+        let tmp_path = path.with_extension("toml.tmp");
+        fs::write(&tmp_path, toml::to_string(self)?)
+            .with_context(|| format!("writing {:?}", tmp_path))?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
+
+/// Stores the derived seed bytes as a hex string.
+mod seed_hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(seed: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&seed.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(D::Error::custom("seed must be 64 hex digits"));
+        }
+        let mut seed = [0; 32];
+        for (i, byte) in seed.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(D::Error::custom)?;
+        }
+        Ok(seed)
+    }
+}
+
+/// Upgrades a level descriptor written by an older version, one version at a time.
+fn migrate(table: toml::Table) -> Result<toml::Table> {
+    let version = match table.get("version") {
+        Some(toml::Value::Integer(version)) => *version as u32,
+        _ => bail!("level descriptor has no version"),
+    };
+    match version {
+        LEVEL_FORMAT_VERSION => Ok(table),
+        // future format changes add a step here, e.g.
+        // 1 => migrate(v1_to_v2(table)),
+        version if version > LEVEL_FORMAT_VERSION => {
+            bail!("level format version {} is newer than supported version {}", version, LEVEL_FORMAT_VERSION)
+        }
+        version => bail!("no migration from level format version {}", version),
+    }
+}
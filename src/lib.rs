@@ -3,13 +3,30 @@ mod texture;
 mod block;
 pub mod state;
 mod world;
+mod section;
+mod streaming;
+mod frustum;
+mod player;
+mod interaction;
+mod block_update;
+mod fluid;
+mod region;
+mod level;
+mod generation;
+mod biome;
+mod caves;
+mod ore;
+mod decoration;
+mod mesher;
 mod time;
+#[cfg(test)]
+mod test_util;
 pub mod camera;
 
 
 
 #[repr(u8)]
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Cardinal {
     Up = 0,
     Down,
@@ -19,17 +36,66 @@ pub enum Cardinal {
     West,
 }
 
+impl Cardinal {
+    pub const ALL: [Cardinal; 6] = [
+        Cardinal::Up,
+        Cardinal::Down,
+        Cardinal::North,
+        Cardinal::South,
+        Cardinal::East,
+        Cardinal::West,
+    ];
 
+    /// Unit step towards the face, in the storage axes `[x, y, z]` chunks index
+    /// blocks with.
+    pub fn normal(self) -> [i64; 3] {
+        match self {
+            Cardinal::Up => [0, 1, 0],
+            Cardinal::Down => [0, -1, 0],
+            Cardinal::North => [1, 0, 0],
+            Cardinal::South => [-1, 0, 0],
+            Cardinal::East => [0, 0, -1],
+            Cardinal::West => [0, 0, 1],
+        }
+    }
+}
+
+
+/// Vertex as stored in chunk vertex buffers, decoded in `shader.wgsl`.
+///
+/// - bits 0..5, 5..14 and 14..19 of the first word are the x, y and z position inside
+///   the chunk, bits 19..24 and 24..29 the u and v texture coordinates, and bits
+///   29..32 how many eighths of a block the vertex is lowered by, which puts fluid
+///   surfaces below the top of their block
+/// - bits 0..16 of the second word are the texture layer as a 16 bit fraction of the
+///   texture depth, and bits 16..25 the tint: the column `x * 16 + z` of the chunk
+///   whose biome tint colours the face, or [`PackedVertex::UNTINTED`]
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: [f32; 3],
-    tex_coord: [f32; 3],
+#[derive(Copy, Clone, Debug, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+struct PackedVertex {
+    data: [u32; 2],
 }
 
-impl Vertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 2] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+impl PackedVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Uint32x2];
+
+    /// Tint of faces that are not coloured by their biome.
+    const UNTINTED: u16 = 256;
+
+    /// `position` is in chunk storage axes, x and z in 0..=16, y in 0..=256, and
+    /// texture coordinates repeat every unit up to 31. `drop` is in eighths of a
+    /// block, up to 7.
+    fn new(position: [u32; 3], tex_coord: [u32; 2], texture: u16, tint: u16, drop: u8) -> Self {
+        let [x, y, z] = position;
+        let [u, v] = tex_coord;
+        debug_assert!(x <= 16 && y <= 256 && z <= 16 && u < 32 && v < 32 && tint <= Self::UNTINTED && drop < 8);
+        Self {
+            data: [
+                x | y << 5 | z << 14 | u << 19 | v << 24 | (drop as u32) << 29,
+                texture as u32 | (tint as u32) << 16,
+            ],
+        }
+    }
 
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
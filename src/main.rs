@@ -23,79 +23,76 @@ fn main() {
     window.set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
     window.set_cursor_visible(false);
 
-    runtime.block_on(async {
-        let mut state = State::new(window).await;
-        state.configure();
+    let mut state = match runtime.block_on(State::new(window)) {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    state.configure();
 
-        runtime.spawn(async move {
-            'main_loop: loop {
-                let event = event_rx.recv().await.unwrap();
-                match &event {
-                    Event::LoopExiting => {
-                        break 'main_loop;
-                    }
-                    Event::DeviceEvent { event, .. } => match event {
-                        DeviceEvent::MouseMotion { delta } => {
-                            state.camera_controller.process_mouse(delta.0, delta.1);
-                        }
-                        _ => {}
-                    },
-                    Event::WindowEvent { event, .. } => {
-                        if !state.input(event) {
-                            match event {
-                                WindowEvent::RedrawRequested => {
-                                    state.time.set_frame_start_time();
-                                    state.window.request_redraw();
-                                    state.update().await;
-                                    match state.render().await {
-                                        Ok(_) => (),
-                                        Err(wgpu::SurfaceError::OutOfMemory) => {
-                                            log::error!("Out of memory")
-                                        }
-                                        Err(wgpu::SurfaceError::Timeout) => {
-                                            log::warn!("Surface Timeout")
-                                        }
-                                        Err(
-                                            wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
-                                        ) => state.resize(state.size),
-                                    }
-                                    state.time.update_frame_time();
-                                    log::info!("frame time: {}ms", state.time.frame_time.as_secs_f32() * 1000.0, );
-                                }
-                                WindowEvent::Resized(size) => {
-                                    state.resize(*size);
-                                }
-                                _ => (),
+    let main_loop = runtime.spawn(async move {
+        'main_loop: loop {
+            let event = event_rx.recv().await.unwrap();
+            match &event {
+                Event::LoopExiting => {
+                    state.save().await;
+                    break 'main_loop;
+                }
+                Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
+                    state.camera_controller.process_mouse(delta.0, delta.1);
+                }
+                Event::WindowEvent { event, .. } if !state.input(event) => match event {
+                    WindowEvent::RedrawRequested => {
+                        state.time.set_frame_start_time();
+                        state.window.request_redraw();
+                        state.update().await;
+                        match state.render().await {
+                            Ok(_) => (),
+                            Err(wgpu::SurfaceError::OutOfMemory) => {
+                                log::error!("Out of memory")
                             }
+                            Err(wgpu::SurfaceError::Timeout) => {
+                                log::warn!("Surface Timeout")
+                            }
+                            Err(
+                                wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
+                            ) => state.resize(state.size),
                         }
+                        state.time.update_frame_time();
+                        log::info!("frame time: {}ms", state.time.frame_time.as_secs_f32() * 1000.0, );
+                    }
+                    WindowEvent::Resized(size) => {
+                        state.resize(*size);
                     }
                     _ => (),
-                }
+                },
+                _ => (),
             }
-        });
+        }
     });
 
     let _ = event_loop.run(move |event, control_flow| {
-        match &event {
-            Event::WindowEvent {
-                event:
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        event:
-                            KeyEvent {
-                                state: ElementState::Pressed,
-                                physical_key: PhysicalKey::Code(KeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    },
-                ..
-            } => control_flow.exit(),
-            // Event::WindowEvent { window_id: _, event: WindowEvent::RedrawRequested } => {
-            //     window.request_redraw();
-            // } 
-            _ => (),
+        if let Event::WindowEvent {
+            event:
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            state: ElementState::Pressed,
+                            physical_key: PhysicalKey::Code(KeyCode::Escape),
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } = &event
+        {
+            control_flow.exit()
         }
         event_tx.send(event).unwrap();
     });
+    // let the main loop finish saving the world before the runtime shuts down
+    let _ = runtime.block_on(main_loop);
 }
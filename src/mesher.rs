@@ -0,0 +1,399 @@
+use crate::{
+    block::{Block, BlockRegistry, Transparency},
+    fluid::FULL,
+    section::SECTION_SIZE,
+    world::Chunk,
+    Cardinal, PackedVertex,
+};
+
+const FACES: [Cardinal; 6] = [
+    Cardinal::Up,
+    Cardinal::Down,
+    Cardinal::North,
+    Cardinal::South,
+    Cardinal::East,
+    Cardinal::West,
+];
+
+/// Environment variable selecting the mesher, "naive" or "greedy".
+pub const MESHING_MODE_VAR: &str = "MESHING_MODE";
+
+/// How the visible faces of a chunk are turned into geometry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeshingMode {
+    /// two triangles for every visible face
+    Naive,
+    /// coplanar neighbouring faces that look the same are merged into larger quads
+    #[default]
+    Greedy,
+}
+
+impl MeshingMode {
+    /// Mode named by `MESHING_MODE`, the default if it is unset or unknown.
+    pub fn from_env() -> Self {
+        match std::env::var(MESHING_MODE_VAR).as_deref() {
+            Ok("naive") => MeshingMode::Naive,
+            Ok("greedy") => MeshingMode::Greedy,
+            Ok(other) => {
+                log::warn!("unknown {} \"{}\", using the default mesher", MESHING_MODE_VAR, other);
+                MeshingMode::default()
+            }
+            Err(_) => MeshingMode::default(),
+        }
+    }
+}
+
+/// Upper bound on the quads of one vertex buffer: every block of a section showing
+/// all six faces, which happens for a section full of leaves.
+pub const MAX_QUADS: usize = SECTION_SIZE * SECTION_SIZE * SECTION_SIZE * 6;
+
+/// Index pattern of the quad made of vertices `4 * quad .. 4 * quad + 4`. All quads
+/// share one index buffer built from it.
+pub fn quad_indices(quad: u32) -> [u32; 6] {
+    let first = quad * 4;
+    [first, first + 1, first + 2, first, first + 2, first + 3]
+}
+
+/// Packed vertices of a meshed chunk, four per quad, relative to the chunk origin and
+/// ready to be uploaded.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    /// opaque and cutout faces
+    pub opaque: Vec<PackedVertex>,
+    /// faces drawn in the blended pass
+    pub translucent: Vec<PackedVertex>,
+}
+
+impl MeshData {
+    /// Number of vertices.
+    pub fn len(&self) -> usize {
+        self.opaque.len() + self.translucent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds the quad covering `size` blocks from `point` on the `face` side of them.
+    /// Texture coordinates are scaled with the quad so textures repeat once per block
+    /// instead of stretching. Corners at the top of the blocks are lowered by
+    /// `key.drop` eighths of a block.
+    fn push_quad(&mut self, face: Cardinal, key: FaceKey, point: [usize; 3], size: [usize; 3]) {
+        let vertices = if key.translucent { &mut self.translucent } else { &mut self.opaque };
+        let (corners, [u_axis, v_axis]) = face_corners(face);
+        let texture = (key.texture_id * 65535.0).round() as u16;
+        // the merged faces share their tint, the first column's stands for all of them
+        let tint = key.tint.map_or(PackedVertex::UNTINTED, |_| (point[0] * 16 + point[2]) as u16);
+        vertices.extend(corners.iter().zip(TEX_COORDS).map(|(corner, [u, v])| {
+            let position = [0, 1, 2].map(|axis| (point[axis] + corner[axis] * size[axis]) as u32);
+            let tex_coord = [u * size[u_axis] as u32, v * size[v_axis] as u32];
+            let drop = if corner[1] == 1 { key.drop } else { 0 };
+            PackedVertex::new(position, tex_coord, texture, tint, drop)
+        }));
+    }
+}
+
+/// Texture coordinates of the corners returned by [`face_corners`], v grows downwards.
+const TEX_COORDS: [[u32; 2]; 4] = [[0, 1], [0, 0], [1, 0], [1, 1]];
+
+/// The corners of one face of a block as offsets in storage axes, ordered bottom left,
+/// top left, top right and bottom right as the texture is seen, which [`quad_indices`]
+/// turns into two triangles facing out. Also returns the storage axes the u and v
+/// texture coordinates run along.
+fn face_corners(face: Cardinal) -> ([[usize; 3]; 4], [usize; 2]) {
+    match face {
+        Cardinal::Up => ([[0, 1, 0], [1, 1, 0], [1, 1, 1], [0, 1, 1]], [2, 0]),
+        Cardinal::Down => ([[0, 0, 1], [1, 0, 1], [1, 0, 0], [0, 0, 0]], [2, 0]),
+        Cardinal::North => ([[1, 0, 1], [1, 1, 1], [1, 1, 0], [1, 0, 0]], [2, 1]),
+        Cardinal::South => ([[0, 0, 0], [0, 1, 0], [0, 1, 1], [0, 0, 1]], [2, 1]),
+        Cardinal::East => ([[1, 0, 0], [1, 1, 0], [0, 1, 0], [0, 0, 0]], [0, 1]),
+        Cardinal::West => ([[0, 0, 1], [0, 1, 1], [1, 1, 1], [1, 0, 1]], [0, 1]),
+    }
+}
+
+/// Meshes one 16 block high section of a chunk on the CPU. `side_blocks` are the
+/// border slices of the neighbouring chunks touching this one, in the order north,
+/// south, east, west, as returned by [`Chunk::get_side_blocks`] for the opposite side
+/// of each neighbour. Positions stay relative to the chunk, not the section.
+pub fn mesh_section(
+    chunk: &Chunk,
+    section: usize,
+    side_blocks: &[[[Block; 16]; 256]; 4],
+    registry: &BlockRegistry,
+    mode: MeshingMode,
+) -> MeshData {
+    if !chunk.has_section(section) {
+        return MeshData::default();
+    }
+    let bottom = section * SECTION_SIZE;
+    match mode {
+        MeshingMode::Naive => naive_mesh(chunk, bottom, side_blocks, registry),
+        MeshingMode::Greedy => greedy_mesh(chunk, bottom, side_blocks, registry),
+    }
+}
+
+/// One quad for every visible face.
+fn naive_mesh(chunk: &Chunk, bottom: usize, side_blocks: &[[[Block; 16]; 256]; 4], registry: &BlockRegistry) -> MeshData {
+    let mut mesh = MeshData::default();
+    for y in bottom..bottom + DIMENSIONS[1] {
+        for x in 0..DIMENSIONS[0] {
+            for z in 0..DIMENSIONS[2] {
+                if chunk.get_block(x, y, z) == Block::AIR {
+                    continue;
+                }
+                for face in FACES {
+                    if let Some(key) = face_key(chunk, side_blocks, registry, [x, y, z], face) {
+                        mesh.push_quad(face, key, [x, y, z], [1, 1, 1]);
+                    }
+                }
+            }
+        }
+    }
+    mesh
+}
+
+/// Size of a section along the storage axes x, y and z.
+const DIMENSIONS: [usize; 3] = [16, SECTION_SIZE, 16];
+
+/// What a face looks like. Faces with equal keys can be merged into one quad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FaceKey {
+    texture_id: f32,
+    /// the biome tint varies slightly between every column, rounding it to what the
+    /// screen can show lets neighbouring grass faces merge, `None` for faces that are
+    /// not tinted
+    tint: Option<[u8; 3]>,
+    translucent: bool,
+    /// eighths of a block the top of the face is lowered by, see [`surface_drop`]
+    drop: u8,
+}
+
+/// Greedy mesher.
+///
+/// Every face direction is meshed one layer at a time: the visible faces of the layer
+/// are collected in a mask, then rectangles of equal faces are grown first along one
+/// axis of the layer and then along the other. Texture coordinates are scaled with
+/// the quad, see [`MeshData::push_quad`].
+fn greedy_mesh(chunk: &Chunk, bottom: usize, side_blocks: &[[[Block; 16]; 256]; 4], registry: &BlockRegistry) -> MeshData {
+    let mut mesh = MeshData::default();
+    let dimensions = DIMENSIONS;
+
+    for face in FACES {
+        // normal axis and the two axes spanning a layer, as indices into [x, y, z]
+        let (normal, a, b) = match face {
+            Cardinal::Up | Cardinal::Down => (1, 0, 2),
+            Cardinal::North | Cardinal::South => (0, 1, 2),
+            Cardinal::East | Cardinal::West => (2, 1, 0),
+        };
+        let (size_a, size_b) = (dimensions[a], dimensions[b]);
+        let mut mask: Vec<Option<FaceKey>> = vec![None; size_a * size_b];
+
+        for layer in 0..dimensions[normal] {
+            for i in 0..size_a {
+                for j in 0..size_b {
+                    let mut point = [0; 3];
+                    point[normal] = layer;
+                    point[a] = i;
+                    point[b] = j;
+                    point[1] += bottom;
+                    mask[i * size_b + j] = face_key(chunk, side_blocks, registry, point, face);
+                }
+            }
+
+            for i in 0..size_a {
+                let mut j = 0;
+                while j < size_b {
+                    let Some(key) = mask[i * size_b + j] else {
+                        j += 1;
+                        continue;
+                    };
+                    let mut width = 1;
+                    while j + width < size_b && mask[i * size_b + j + width] == Some(key) {
+                        width += 1;
+                    }
+                    let mut height = 1;
+                    'grow: while i + height < size_a {
+                        for k in 0..width {
+                            if mask[(i + height) * size_b + j + k] != Some(key) {
+                                break 'grow;
+                            }
+                        }
+                        height += 1;
+                    }
+                    for row in i..i + height {
+                        mask[row * size_b + j..row * size_b + j + width].fill(None);
+                    }
+
+                    let mut point = [0; 3];
+                    point[normal] = layer;
+                    point[a] = i;
+                    point[b] = j;
+                    point[1] += bottom;
+                    let mut size = [1; 3];
+                    size[a] = height;
+                    size[b] = width;
+                    mesh.push_quad(face, key, point, size);
+
+                    j += width;
+                }
+            }
+        }
+    }
+    mesh
+}
+
+/// The block next to a block of the chunk, looking past the border into the
+/// neighbouring chunks.
+fn neighbour(chunk: &Chunk, side_blocks: &[[[Block; 16]; 256]; 4], [x, y, z]: [usize; 3], face: Cardinal) -> Block {
+    match face {
+        Cardinal::Up if y < 255 => chunk.get_block(x, y + 1, z),
+        Cardinal::Down if y > 0 => chunk.get_block(x, y - 1, z),
+        Cardinal::Up | Cardinal::Down => Block::AIR,
+        Cardinal::North if x < 15 => chunk.get_block(x + 1, y, z),
+        Cardinal::North => side_blocks[0][y][z],
+        Cardinal::South if x > 0 => chunk.get_block(x - 1, y, z),
+        Cardinal::South => side_blocks[1][y][z],
+        Cardinal::East if z > 0 => chunk.get_block(x, y, z - 1),
+        Cardinal::East => side_blocks[2][y][x],
+        Cardinal::West if z < 15 => chunk.get_block(x, y, z + 1),
+        Cardinal::West => side_blocks[3][y][x],
+    }
+}
+
+fn face_key(
+    chunk: &Chunk,
+    side_blocks: &[[[Block; 16]; 256]; 4],
+    registry: &BlockRegistry,
+    point: [usize; 3],
+    face: Cardinal,
+) -> Option<FaceKey> {
+    let [x, y, z] = point;
+    let block = chunk.get_block(x, y, z);
+    let definition = registry.get(block);
+    let textures = definition.textures?;
+    if !registry.is_face_visible(block, neighbour(chunk, side_blocks, point, face), face) {
+        return None;
+    }
+    Some(FaceKey {
+        texture_id: textures[face as usize],
+        tint: definition.is_tinted(face).then(|| pack_tint(chunk.biomes.tints[x][z])),
+        translucent: definition.transparency == Transparency::Translucent,
+        drop: surface_drop(chunk, side_blocks, registry, point),
+    })
+}
+
+/// A biome tint as 8 bit red, green and blue, as the tint uniform of a chunk holds it.
+pub fn pack_tint(tint: [f32; 3]) -> [u8; 3] {
+    tint.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+/// How far below the top of its block the surface of a fluid lies, in eighths of a
+/// block. The surface follows the fluid level but stays an eighth below the top even
+/// for sources, so it sits lower than the ground around it. Fluid with more of
+/// itself above fills its block.
+fn surface_drop(chunk: &Chunk, side_blocks: &[[[Block; 16]; 256]; 4], registry: &BlockRegistry, point: [usize; 3]) -> u8 {
+    let [x, y, z] = point;
+    let Some(fluid) = registry.get(chunk.get_block(x, y, z)).fluid else {
+        return 0;
+    };
+    let above = registry.get(neighbour(chunk, side_blocks, point, Cardinal::Up)).fluid;
+    if above.is_some_and(|above| above.kind == fluid.kind) {
+        return 0;
+    }
+    FULL - fluid.level.min(FULL - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::registry;
+
+    const NO_SIDES: [[[Block; 16]; 256]; 4] = [[[Block::AIR; 16]; 256]; 4];
+
+    fn chunk_with(blocks: &[[usize; 3]]) -> Chunk {
+        let mut chunk = Chunk::new((0.0, 0.0));
+        for &[x, y, z] in blocks {
+            chunk.set_block(x, y, z, Block::STONE);
+        }
+        chunk
+    }
+
+    /// Quads meshed for the section at y 16..32 in both modes, naive first.
+    fn quads(chunk: &Chunk, side_blocks: &[[[Block; 16]; 256]; 4]) -> [usize; 2] {
+        let registry = registry();
+        [MeshingMode::Naive, MeshingMode::Greedy].map(|mode| {
+            let mesh = mesh_section(chunk, 1, side_blocks, &registry, mode);
+            assert!(mesh.translucent.is_empty());
+            assert_eq!(mesh.len() % 4, 0);
+            mesh.len() / 4
+        })
+    }
+
+    #[test]
+    fn single_block() {
+        let chunk = chunk_with(&[[5, 20, 7]]);
+        assert_eq!(quads(&chunk, &NO_SIDES), [6, 6]);
+        // both modes give the same quad for a lone face
+        let registry = registry();
+        let naive = mesh_section(&chunk, 1, &NO_SIDES, &registry, MeshingMode::Naive);
+        let greedy = mesh_section(&chunk, 1, &NO_SIDES, &registry, MeshingMode::Greedy);
+        for (naive, greedy) in naive.opaque.chunks(4).zip(greedy.opaque.chunks(4)) {
+            assert!((0..4).any(|turn| (0..4).all(|i| naive[i] == greedy[(i + turn) % 4])));
+        }
+    }
+
+    #[test]
+    fn slab_merges_into_one_quad_per_face() {
+        let chunk = chunk_with(&[[4, 20, 4], [4, 20, 5], [5, 20, 4], [5, 20, 5]]);
+        // 4 faces on top, 4 below and 2 on each side
+        assert_eq!(quads(&chunk, &NO_SIDES), [16, 6]);
+    }
+
+    #[test]
+    fn tinted_faces_index_their_column() {
+        let mut chunk = Chunk::new((0.0, 0.0));
+        chunk.set_block(3, 20, 9, Block::GRASS);
+        let mesh = mesh_section(&chunk, 1, &NO_SIDES, &registry(), MeshingMode::Greedy);
+        for quad in mesh.opaque.chunks(4) {
+            // only the top face of grass is tinted, it lies at the top of the block
+            let top = quad.iter().all(|vertex| (vertex.data[0] >> 5) & 511 == 21);
+            let tint = if top { 3 * 16 + 9 } else { PackedVertex::UNTINTED };
+            assert!(quad.iter().all(|vertex| vertex.data[1] >> 16 == tint as u32));
+        }
+    }
+
+    #[test]
+    fn empty_sections_have_no_quads() {
+        let chunk = chunk_with(&[[5, 40, 7]]);
+        assert_eq!(quads(&chunk, &NO_SIDES), [0, 0]);
+    }
+
+    #[test]
+    fn neighbouring_chunks_hide_border_faces() {
+        // in the corner of the chunk touching the north and east neighbours
+        let chunk = chunk_with(&[[15, 20, 0]]);
+        assert_eq!(quads(&chunk, &NO_SIDES), [6, 6]);
+
+        let mut side_blocks = NO_SIDES;
+        side_blocks[0][20][0] = Block::STONE;
+        side_blocks[2][20][15] = Block::STONE;
+        assert_eq!(quads(&chunk, &side_blocks), [4, 4]);
+
+        // blocks beside the ones touching the border change nothing
+        let mut side_blocks = NO_SIDES;
+        side_blocks[0][21][0] = Block::STONE;
+        side_blocks[2][20][14] = Block::STONE;
+        assert_eq!(quads(&chunk, &side_blocks), [6, 6]);
+    }
+
+    #[test]
+    fn border_row_hidden_by_neighbour() {
+        let chunk = chunk_with(&[[15, 20, 3], [15, 20, 4], [15, 20, 5]]);
+        // 3 faces each on top, below and north, 1 on each end and 3 facing south
+        assert_eq!(quads(&chunk, &NO_SIDES), [14, 6]);
+
+        let mut side_blocks = NO_SIDES;
+        side_blocks[0][20][3..6].fill(Block::STONE);
+        assert_eq!(quads(&chunk, &side_blocks), [11, 5]);
+    }
+}
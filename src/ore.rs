@@ -0,0 +1,100 @@
+use noise::NoiseFn;
+use rand::Rng;
+
+use crate::{block::Block, generation::NoiseContext, world::Chunk};
+
+/// Frequency of the regional ore richness noise, in chunk units.
+const RICHNESS_FREQUENCY: f64 = 0.1;
+/// How much the richness noise can raise or lower the number of veins in a chunk.
+const RICHNESS_VARIATION: f64 = 0.5;
+
+/// Placement rules of one ore.
+#[derive(Debug, Clone, Copy)]
+pub struct OreConfig {
+    pub block: Block,
+    /// lowest and highest layer a vein can start on
+    pub min_height: usize,
+    pub max_height: usize,
+    /// number of blocks in a vein
+    pub vein_size: u32,
+    /// average number of veins per chunk
+    pub veins_per_chunk: f64,
+}
+
+pub const DEFAULT_ORES: [OreConfig; 5] = [
+    OreConfig {
+        block: Block::COAL_ORE,
+        min_height: 5,
+        max_height: 160,
+        vein_size: 14,
+        veins_per_chunk: 18.0,
+    },
+    OreConfig {
+        block: Block::COPPER_ORE,
+        min_height: 5,
+        max_height: 110,
+        vein_size: 10,
+        veins_per_chunk: 10.0,
+    },
+    OreConfig {
+        block: Block::IRON_ORE,
+        min_height: 5,
+        max_height: 90,
+        vein_size: 8,
+        veins_per_chunk: 12.0,
+    },
+    OreConfig {
+        block: Block::GOLD_ORE,
+        min_height: 3,
+        max_height: 40,
+        vein_size: 7,
+        veins_per_chunk: 2.5,
+    },
+    OreConfig {
+        block: Block::DIAMOND_ORE,
+        min_height: 2,
+        max_height: 20,
+        vein_size: 5,
+        veins_per_chunk: 1.0,
+    },
+];
+
+/// Places ore veins inside the stone of a chunk.
+///
+/// Veins never leave the chunk they start in and the random numbers only depend on
+/// the seed and the chunk position, so the result does not depend on the order
+/// chunks are generated in.
+pub fn place_ores(noise: &NoiseContext, chunk: &mut Chunk, position: (i64, i64), ores: &[OreConfig]) {
+    let mut rng = noise.chunk_rng("ores", position);
+    let richness = 1.0
+        + RICHNESS_VARIATION
+            * noise.ores.get([
+                RICHNESS_FREQUENCY * position.0 as f64,
+                RICHNESS_FREQUENCY * position.1 as f64,
+            ]);
+
+    for ore in ores {
+        // the fractional part of the expected vein count is a chance for one more vein
+        let expected = ore.veins_per_chunk * richness;
+        let veins = expected.floor() as u32 + rng.random_bool(expected.fract()) as u32;
+        for _ in 0..veins {
+            let mut point: [i32; 3] = [
+                rng.random_range(0..16),
+                rng.random_range(ore.min_height..=ore.max_height) as i32,
+                rng.random_range(0..16),
+            ];
+            for _ in 0..ore.vein_size {
+                let (x, y, z) = (point[0], point[1], point[2]);
+                if (0..16).contains(&x) && (1..256).contains(&y) && (0..16).contains(&z) {
+                    let (x, y, z) = (x as usize, y as usize, z as usize);
+                    if chunk.get_block(x, y, z) == Block::STONE {
+                        chunk.set_block(x, y, z, ore.block);
+                    }
+                }
+                // step to a random face neighbour
+                let axis = rng.random_range(0..3);
+                point[axis] += if rng.random_bool(0.5) { 1 } else { -1 };
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use cgmath::{Point3, Vector3};
+
+use crate::frustum::Aabb;
+
+/// Width of the player on both horizontal axes, in blocks.
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+/// Height of the camera above the feet of the player.
+pub const EYE_HEIGHT: f32 = 1.62;
+
+/// Space the player takes up with the camera at `eye`, in render coordinates.
+pub fn player_bounds(eye: Point3<f32>) -> Aabb {
+    let half = PLAYER_WIDTH / 2.0;
+    Aabb::new(
+        Vector3::new(eye.x - half, eye.y - EYE_HEIGHT, eye.z - half),
+        Vector3::new(eye.x + half, eye.y - EYE_HEIGHT + PLAYER_HEIGHT, eye.z + half),
+    )
+}
+
+/// Downward acceleration while walking, in blocks per second squared.
+pub const GRAVITY: f32 = 32.0;
+/// Fastest the player falls, in blocks per second.
+pub const TERMINAL_VELOCITY: f32 = 60.0;
+/// How high a jump lifts the feet of the player, in blocks.
+pub const JUMP_HEIGHT: f32 = 1.25;
+pub const WALK_SPEED: f32 = 4.3;
+/// Highest ledge the player walks onto without jumping. Terrain is made of full
+/// blocks, so this lets the player climb single block steps.
+pub const STEP_HEIGHT: f32 = 1.0;
+
+/// Boxes closer than this are treated as touching, so rounding errors do not let
+/// the player sink into the ground or snag on the edge of the block below.
+const EPSILON: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementMode {
+    /// moves freely through the world
+    Fly,
+    /// falls, jumps and collides with solid blocks
+    Walk,
+}
+
+/// Movement state of the player. The position is the camera's, the player's feet are
+/// `EYE_HEIGHT` below it.
+#[derive(Debug, Clone)]
+pub struct Player {
+    pub mode: MovementMode,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self {
+            mode: MovementMode::Fly,
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+            on_ground: false,
+        }
+    }
+}
+
+impl Player {
+    /// Switches between flying and walking. The player starts either mode at rest.
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            MovementMode::Fly => MovementMode::Walk,
+            MovementMode::Walk => MovementMode::Fly,
+        };
+        self.velocity = Vector3::new(0.0, 0.0, 0.0);
+        self.on_ground = false;
+    }
+
+    /// Advances walking by `dt` seconds and returns the new camera position.
+    /// `walk` is the horizontal direction the player wants to go in render
+    /// coordinates, at most one long, and `solid` tells whether the block at a
+    /// position in storage axes `[x, y, z]` blocks movement.
+    ///
+    /// Collisions are resolved by sweeping the player's box along one axis at a time,
+    /// vertical first, so the player can not pass through blocks however far it moves
+    /// in a step. Blocks the box already overlaps are ignored, which lets a player
+    /// stuck inside terrain walk out of it.
+    pub fn step(
+        &mut self,
+        eye: Point3<f32>,
+        walk: Vector3<f32>,
+        jump: bool,
+        dt: f32,
+        solid: impl Fn([i64; 3]) -> bool,
+    ) -> Point3<f32> {
+        self.velocity.x = walk.x * WALK_SPEED;
+        self.velocity.z = walk.z * WALK_SPEED;
+        if jump && self.on_ground {
+            self.velocity.y = (2.0 * GRAVITY * JUMP_HEIGHT).sqrt();
+        }
+        self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+
+        let motion = self.velocity * dt;
+        let bounds = player_bounds(eye);
+        let mut moved = move_bounds(bounds, motion, &solid);
+        let blocked = moved.x != motion.x || moved.z != motion.z;
+        if self.on_ground && blocked {
+            // try again from up to a step higher and settle back down onto the ledge
+            let up = sweep(bounds, 1, STEP_HEIGHT, &solid);
+            let raised = offset(bounds, Vector3::new(0.0, up, 0.0));
+            let across = move_bounds(raised, Vector3::new(motion.x, 0.0, motion.z), &solid);
+            let down = sweep(offset(raised, across), 1, motion.y.min(0.0) - up, &solid);
+            let stepped = Vector3::new(across.x, up + down, across.z);
+            if stepped.x * stepped.x + stepped.z * stepped.z > moved.x * moved.x + moved.z * moved.z {
+                moved = stepped;
+            }
+        }
+
+        self.on_ground = motion.y < 0.0 && moved.y > motion.y;
+        if moved.y != motion.y {
+            self.velocity.y = 0.0;
+        }
+        eye + moved
+    }
+}
+
+/// Moves `bounds` by `motion` as far as solid blocks allow, one axis at a time, and
+/// returns how far it got.
+fn move_bounds(bounds: Aabb, motion: Vector3<f32>, solid: &impl Fn([i64; 3]) -> bool) -> Vector3<f32> {
+    let mut moved = Vector3::new(0.0, 0.0, 0.0);
+    for axis in [1, 0, 2] {
+        moved[axis] = sweep(offset(bounds, moved), axis, motion[axis], solid);
+    }
+    moved
+}
+
+/// How far `bounds` can move along a render axis, up to `distance`, before it runs
+/// into a solid block.
+fn sweep(bounds: Aabb, axis: usize, distance: f32, solid: &impl Fn([i64; 3]) -> bool) -> f32 {
+    if distance == 0.0 {
+        return 0.0;
+    }
+    // blocks in front of the box that the move reaches
+    let along = if distance > 0.0 {
+        (bounds.max[axis] - EPSILON).ceil() as i64..(bounds.max[axis] + distance).ceil() as i64
+    } else {
+        (bounds.min[axis] + distance).floor() as i64..(bounds.min[axis] + EPSILON).floor() as i64
+    };
+    let [first, second] = match axis {
+        0 => [1, 2],
+        1 => [0, 2],
+        _ => [0, 1],
+    };
+    let across = |axis: usize| (bounds.min[axis] + EPSILON).floor() as i64..(bounds.max[axis] - EPSILON).ceil() as i64;
+
+    let mut distance = distance;
+    for a in across(first) {
+        for b in across(second) {
+            for cell in along.clone() {
+                let mut position = [0; 3];
+                position[axis] = cell;
+                position[first] = a;
+                position[second] = b;
+                // render axes to storage axes
+                if !solid([position[2], position[1] + 60, position[0]]) {
+                    continue;
+                }
+                if distance > 0.0 {
+                    let gap = cell as f32 - bounds.max[axis];
+                    if gap > -EPSILON {
+                        distance = distance.min(gap.max(0.0));
+                    }
+                } else {
+                    let gap = bounds.min[axis] - (cell + 1) as f32;
+                    if gap > -EPSILON {
+                        distance = distance.max(-gap.max(0.0));
+                    }
+                }
+            }
+        }
+    }
+    distance
+}
+
+fn offset(bounds: Aabb, by: Vector3<f32>) -> Aabb {
+    Aabb::new(bounds.min + by, bounds.max + by)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    /// Camera height of a player standing on the ground below render y 5, which is the
+    /// top of storage y 64.
+    const STANDING: f32 = 5.0 + EYE_HEIGHT;
+
+    fn ground([_, y, _]: [i64; 3]) -> bool {
+        y <= 64
+    }
+
+    /// Ground with a wall `height` blocks high from render z 10 on.
+    fn wall(height: i64) -> impl Fn([i64; 3]) -> bool {
+        move |[x, y, z]| ground([x, y, z]) || (x >= 10 && y <= 64 + height)
+    }
+
+    fn walking() -> Player {
+        Player { mode: MovementMode::Walk, ..Player::default() }
+    }
+
+    /// Walks along render z for `seconds`.
+    fn walk_for(player: &mut Player, mut eye: Point3<f32>, seconds: f32, solid: &impl Fn([i64; 3]) -> bool) -> Point3<f32> {
+        for _ in 0..(seconds / DT).round() as usize {
+            eye = player.step(eye, Vector3::new(0.0, 0.0, 1.0), false, DT, solid);
+        }
+        eye
+    }
+
+    #[test]
+    fn landing_sets_on_ground() {
+        let mut player = walking();
+        let mut eye = Point3::new(0.5, 20.0, 0.5);
+        eye = player.step(eye, Vector3::new(0.0, 0.0, 0.0), false, DT, ground);
+        assert!(!player.on_ground);
+        for _ in 0..120 {
+            eye = player.step(eye, Vector3::new(0.0, 0.0, 0.0), false, DT, ground);
+        }
+        assert!(player.on_ground);
+        assert_eq!(player.velocity.y, 0.0);
+        assert!((eye.y - STANDING).abs() < 1e-3, "eye at {}", eye.y);
+    }
+
+    #[test]
+    fn walls_stop_motion() {
+        let mut player = walking();
+        let eye = walk_for(&mut player, Point3::new(0.5, STANDING, 5.5), 3.0, &wall(5));
+        assert!((eye.z - (10.0 - PLAYER_WIDTH / 2.0)).abs() < 1e-2, "stopped at {}", eye.z);
+        assert!((eye.y - STANDING).abs() < 1e-3);
+        assert!(player.on_ground);
+    }
+
+    #[test]
+    fn steps_onto_single_blocks() {
+        let mut player = walking();
+        let eye = walk_for(&mut player, Point3::new(0.5, STANDING, 5.5), 3.0, &wall(1));
+        assert!(eye.z > 15.0, "stopped at {}", eye.z);
+        assert!((eye.y - (STANDING + 1.0)).abs() < 1e-3, "eye at {}", eye.y);
+        assert!(player.on_ground);
+    }
+
+    #[test]
+    fn two_block_walls_block() {
+        let mut player = walking();
+        let eye = walk_for(&mut player, Point3::new(0.5, STANDING, 5.5), 3.0, &wall(2));
+        assert!((eye.z - (10.0 - PLAYER_WIDTH / 2.0)).abs() < 1e-2, "stopped at {}", eye.z);
+        assert!((eye.y - STANDING).abs() < 1e-3, "eye at {}", eye.y);
+    }
+
+    #[test]
+    fn long_steps_do_not_tunnel() {
+        // a one block floor far below, reached in a single step at terminal velocity
+        let floor = |[_, y, _]: [i64; 3]| y == 64;
+        let mut player = walking();
+        player.velocity.y = -TERMINAL_VELOCITY;
+        let eye = player.step(Point3::new(0.5, 50.0, 0.5), Vector3::new(0.0, 0.0, 0.0), false, 1.0, floor);
+        assert!((eye.y - STANDING).abs() < 1e-3, "eye at {}", eye.y);
+        assert!(player.on_ground);
+
+        // and a one block thick wall, 43 blocks of walking in a single step
+        let thin = |[x, y, z]: [i64; 3]| floor([x, y, z]) || (x == 10 && y > 64);
+        let eye = player.step(eye, Vector3::new(0.0, 0.0, 1.0), false, 10.0, thin);
+        assert!((eye.z - (10.0 - PLAYER_WIDTH / 2.0)).abs() < 1e-2, "stopped at {}", eye.z);
+    }
+}
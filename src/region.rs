@@ -0,0 +1,355 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::*;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+/// Number of chunks along each side of a region file.
+pub const REGION_SIZE: i64 = 32;
+const CHUNKS_PER_REGION: usize = (REGION_SIZE * REGION_SIZE) as usize;
+const SECTOR_SIZE: u64 = 4096;
+/// Each header entry is a `u32` sector offset followed by a `u32` payload length.
+const HEADER_ENTRY_SIZE: usize = 8;
+const HEADER_SECTORS: u32 = (CHUNKS_PER_REGION * HEADER_ENTRY_SIZE) as u32 / SECTOR_SIZE as u32;
+
+const COMPRESSION_ZLIB: u8 = 1;
+
+/// Location of a chunk payload inside a region file, in sectors.
+#[derive(Debug, Clone, Copy, Default)]
+struct ChunkLocation {
+    sector: u32,
+    length: u32,
+}
+
+impl ChunkLocation {
+    fn sector_count(&self) -> u32 {
+        (self.length as u64).div_ceil(SECTOR_SIZE) as u32
+    }
+    fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+}
+
+/// A single region file holding up to 32x32 chunks.
+///
+/// Layout: a header of 1024 `(sector, length)` entries (little endian) followed by
+/// 4 KiB sectors. Every chunk payload starts on a sector boundary and begins with a
+/// compression id byte.
+pub struct Region {
+    file: File,
+    locations: Vec<ChunkLocation>,
+    used_sectors: Vec<bool>,
+}
+
+impl Region {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("opening region file {:?}", path))?;
+
+        let mut locations = vec![ChunkLocation::default(); CHUNKS_PER_REGION];
+        let mut used_sectors = vec![true; HEADER_SECTORS as usize];
+        if file.metadata()?.len() < HEADER_SECTORS as u64 * SECTOR_SIZE {
+            file.set_len(HEADER_SECTORS as u64 * SECTOR_SIZE)?;
+        } else {
+            let mut header = vec![0; CHUNKS_PER_REGION * HEADER_ENTRY_SIZE];
+            file.seek(SeekFrom::Start(0))?;
+            file.read_exact(&mut header)?;
+            for (location, entry) in locations.iter_mut().zip(header.chunks_exact(HEADER_ENTRY_SIZE)) {
+                location.sector = u32::from_le_bytes(entry[0..4].try_into().unwrap());
+                location.length = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+                if location.is_empty() {
+                    continue;
+                }
+                let end = (location.sector + location.sector_count()) as usize;
+                if used_sectors.len() < end {
+                    used_sectors.resize(end, false);
+                }
+                used_sectors[location.sector as usize..end].iter_mut().for_each(|x| *x = true);
+            }
+        }
+
+        Ok(Self {
+            file,
+            locations,
+            used_sectors,
+        })
+    }
+
+    /// Index of a chunk inside its region, from region-local coordinates.
+    fn index(local: (usize, usize)) -> usize {
+        local.0 + local.1 * REGION_SIZE as usize
+    }
+
+    pub fn read_chunk(&mut self, local: (usize, usize)) -> Result<Option<Vec<u8>>> {
+        let location = self.locations[Self::index(local)];
+        if location.is_empty() {
+            return Ok(None);
+        }
+        let mut payload = vec![0; location.length as usize];
+        self.file.seek(SeekFrom::Start(location.sector as u64 * SECTOR_SIZE))?;
+        self.file.read_exact(&mut payload)?;
+
+        match payload[0] {
+            COMPRESSION_ZLIB => {
+                let mut data = vec![];
+                ZlibDecoder::new(&payload[1..]).read_to_end(&mut data)?;
+                Ok(Some(data))
+            }
+            id => bail!("unknown chunk compression id {}", id),
+        }
+    }
+
+    pub fn write_chunk(&mut self, local: (usize, usize), data: &[u8]) -> Result<()> {
+        let mut encoder = ZlibEncoder::new(vec![COMPRESSION_ZLIB], Compression::default());
+        encoder.write_all(data)?;
+        let payload = encoder.finish()?;
+
+        let index = Self::index(local);
+        let old = self.locations[index];
+        let new = ChunkLocation {
+            sector: 0,
+            length: payload.len() as u32,
+        };
+        // free the old sectors first so a chunk that shrinks or stays the same size is
+        // rewritten in place
+        self.set_sectors_used(old, false);
+        let location = ChunkLocation {
+            sector: self.find_free_sectors(new.sector_count()),
+            ..new
+        };
+        self.set_sectors_used(location, true);
+
+        self.file.seek(SeekFrom::Start(location.sector as u64 * SECTOR_SIZE))?;
+        self.file.write_all(&payload)?;
+        let padding = location.sector_count() as u64 * SECTOR_SIZE - payload.len() as u64;
+        self.file.write_all(&vec![0; padding as usize])?;
+
+        self.locations[index] = location;
+        let mut entry = [0; HEADER_ENTRY_SIZE];
+        entry[0..4].copy_from_slice(&location.sector.to_le_bytes());
+        entry[4..8].copy_from_slice(&location.length.to_le_bytes());
+        self.file.seek(SeekFrom::Start((index * HEADER_ENTRY_SIZE) as u64))?;
+        self.file.write_all(&entry)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn set_sectors_used(&mut self, location: ChunkLocation, used: bool) {
+        if location.is_empty() {
+            return;
+        }
+        let end = (location.sector + location.sector_count()) as usize;
+        if self.used_sectors.len() < end {
+            self.used_sectors.resize(end, false);
+        }
+        self.used_sectors[location.sector as usize..end].iter_mut().for_each(|x| *x = used);
+    }
+
+    /// First run of `count` free sectors, or the end of the file.
+    fn find_free_sectors(&self, count: u32) -> u32 {
+        let mut run_start = 0;
+        let mut run_length = 0;
+        for (i, used) in self.used_sectors.iter().enumerate() {
+            if *used {
+                run_length = 0;
+                continue;
+            }
+            if run_length == 0 {
+                run_start = i;
+            }
+            run_length += 1;
+            if run_length == count as usize {
+                return run_start as u32;
+            }
+        }
+        if run_length > 0 {
+            run_start as u32
+        } else {
+            self.used_sectors.len() as u32
+        }
+    }
+}
+
+/// All region files of a world, opened lazily from `dir`.
+pub struct RegionStorage {
+    dir: PathBuf,
+    regions: HashMap<(i64, i64), Region>,
+}
+
+impl RegionStorage {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).with_context(|| format!("creating region directory {:?}", dir))?;
+        Ok(Self {
+            dir,
+            regions: HashMap::new(),
+        })
+    }
+
+    /// Splits a chunk position into its region position and the region-local position.
+    fn locate(position: (i64, i64)) -> ((i64, i64), (usize, usize)) {
+        (
+            (position.0.div_euclid(REGION_SIZE), position.1.div_euclid(REGION_SIZE)),
+            (
+                position.0.rem_euclid(REGION_SIZE) as usize,
+                position.1.rem_euclid(REGION_SIZE) as usize,
+            ),
+        )
+    }
+
+    fn region_path(&self, region: (i64, i64)) -> PathBuf {
+        self.dir.join(format!("r.{}.{}.region", region.0, region.1))
+    }
+
+    fn get_region(&mut self, region: (i64, i64), create: bool) -> Result<Option<&mut Region>> {
+        if !self.regions.contains_key(&region) {
+            let path = self.region_path(region);
+            if !create && !path.exists() {
+                return Ok(None);
+            }
+            self.regions.insert(region, Region::open(&path)?);
+        }
+        Ok(self.regions.get_mut(&region))
+    }
+
+    pub fn load_chunk(&mut self, position: (i64, i64)) -> Result<Option<Vec<u8>>> {
+        let (region, local) = Self::locate(position);
+        match self.get_region(region, false)? {
+            Some(region) => region.read_chunk(local),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_chunk(&mut self, position: (i64, i64), data: &[u8]) -> Result<()> {
+        let (region, local) = Self::locate(position);
+        self.get_region(region, true)?.unwrap().write_chunk(local, data)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        for region in self.regions.values_mut() {
+            region.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{block::Block, world::Chunk};
+
+    /// Empty directory under the system temp dir, removed again when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("region-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A chunk of random blocks, which compresses badly and spans many sectors.
+    fn noisy_chunk(seed: u64) -> Chunk {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut chunk = Chunk::new((0.0, 0.0));
+        for y in 0..256 {
+            for x in 0..16 {
+                for z in 0..16 {
+                    chunk.set_block(x, y, z, Block(rng.random_range(0..15)));
+                }
+            }
+        }
+        chunk
+    }
+
+    /// A chunk with a single stone block, small enough for one sector.
+    fn small_chunk(y: usize) -> Chunk {
+        let mut chunk = Chunk::new((0.0, 0.0));
+        chunk.set_block(3, y, 5, Block::STONE);
+        chunk
+    }
+
+    fn reopen(dir: &TempDir, position: (i64, i64)) -> Vec<u8> {
+        let mut storage = RegionStorage::new(&dir.0).unwrap();
+        storage.load_chunk(position).unwrap().expect("chunk was saved")
+    }
+
+    #[test]
+    fn chunk_bytes_round_trip() {
+        let chunk = noisy_chunk(1);
+        let bytes = chunk.to_bytes();
+        assert_eq!(Chunk::from_bytes((0.0, 0.0), &bytes).unwrap().to_bytes(), bytes);
+        assert!(Chunk::from_bytes((0.0, 0.0), &bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn chunks_survive_reopening() {
+        let dir = TempDir::new("reopen");
+        let positions = [(0, 0), (-1, -1), (-33, 5), (40, 2)];
+        {
+            let mut storage = RegionStorage::new(&dir.0).unwrap();
+            for (i, position) in positions.into_iter().enumerate() {
+                storage.save_chunk(position, &small_chunk(i * 10).to_bytes()).unwrap();
+            }
+            storage.flush().unwrap();
+        }
+        // regions (0, 0), (-1, -1), (-2, 0) and (1, 0)
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 4);
+        for (i, position) in positions.into_iter().enumerate() {
+            assert_eq!(reopen(&dir, position), small_chunk(i * 10).to_bytes(), "chunk {:?}", position);
+        }
+        let mut storage = RegionStorage::new(&dir.0).unwrap();
+        assert!(storage.load_chunk((1, 1)).unwrap().is_none());
+        assert!(storage.load_chunk((100, 100)).unwrap().is_none());
+    }
+
+    #[test]
+    fn growing_chunk_is_moved() {
+        let dir = TempDir::new("grow");
+        let big = noisy_chunk(2).to_bytes();
+        {
+            let mut storage = RegionStorage::new(&dir.0).unwrap();
+            storage.save_chunk((-3, 7), &small_chunk(1).to_bytes()).unwrap();
+            // right behind the first chunk, so it can not grow in place
+            storage.save_chunk((-2, 7), &small_chunk(2).to_bytes()).unwrap();
+            storage.save_chunk((-3, 7), &big).unwrap();
+            storage.flush().unwrap();
+        }
+        assert_eq!(reopen(&dir, (-3, 7)), big);
+        assert_eq!(reopen(&dir, (-2, 7)), small_chunk(2).to_bytes());
+
+        // shrinking again frees the sectors for the next chunk
+        {
+            let mut storage = RegionStorage::new(&dir.0).unwrap();
+            storage.save_chunk((-3, 7), &small_chunk(3).to_bytes()).unwrap();
+            storage.save_chunk((-4, 7), &big).unwrap();
+            storage.flush().unwrap();
+        }
+        assert_eq!(reopen(&dir, (-3, 7)), small_chunk(3).to_bytes());
+        assert_eq!(reopen(&dir, (-4, 7)), big);
+        assert_eq!(reopen(&dir, (-2, 7)), small_chunk(2).to_bytes());
+    }
+}
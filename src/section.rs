@@ -0,0 +1,241 @@
+use crate::block::Block;
+
+/// Edge length of a section in blocks.
+pub const SECTION_SIZE: usize = 16;
+/// Number of sections stacked in a chunk.
+pub const SECTIONS: usize = 256 / SECTION_SIZE;
+
+const VOLUME: usize = SECTION_SIZE * SECTION_SIZE * SECTION_SIZE;
+
+/// A 16x16x16 cube of blocks, indexed like chunks with `x, y, z` local to the section.
+/// Chunks only keep sections that contain something other than air.
+///
+/// Blocks are stored as indices into a palette of the block types in the section,
+/// packed into `u64` words with as few bits as the palette needs. Indices never span
+/// two words. A section of a single block type stores no indices at all, and the
+/// usual stone, dirt and ore mix fits in 2 to 4 bits instead of 8.
+#[derive(Debug, Clone)]
+pub struct Section {
+    palette: Vec<Block>,
+    /// bits per index, 0 while the palette has one entry
+    bits: u32,
+    data: Vec<u64>,
+    /// number of blocks that are not air
+    non_air: u16,
+}
+
+impl Default for Section {
+    fn default() -> Self {
+        Self {
+            palette: vec![Block::AIR],
+            bits: 0,
+            data: Vec::new(),
+            non_air: 0,
+        }
+    }
+}
+
+impl Section {
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Block {
+        self.palette[self.index(Self::offset(x, y, z))]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: Block) {
+        let offset = Self::offset(x, y, z);
+        let previous = self.palette[self.index(offset)];
+        if previous == block {
+            return;
+        }
+        let entry = match self.palette.iter().position(|entry| *entry == block) {
+            Some(entry) => entry,
+            None => {
+                self.palette.push(block);
+                if self.palette.len() > 1 << self.bits {
+                    self.resize(self.bits + 1);
+                }
+                self.palette.len() - 1
+            }
+        };
+        self.set_index(offset, entry);
+        match (previous == Block::AIR, block == Block::AIR) {
+            (true, false) => self.non_air += 1,
+            (false, true) => self.non_air -= 1,
+            _ => {}
+        }
+    }
+
+    /// Whether every block is air.
+    pub fn is_empty(&self) -> bool {
+        self.non_air == 0
+    }
+
+    /// Bytes used by the palette and the packed indices.
+    pub fn heap_size(&self) -> usize {
+        self.palette.capacity() * std::mem::size_of::<Block>() + self.data.capacity() * std::mem::size_of::<u64>()
+    }
+
+    fn offset(x: usize, y: usize, z: usize) -> usize {
+        (y * SECTION_SIZE + x) * SECTION_SIZE + z
+    }
+
+    fn index(&self, offset: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = 64 / self.bits as usize;
+        let shift = (offset % per_word) as u32 * self.bits;
+        ((self.data[offset / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set_index(&mut self, offset: usize, index: usize) {
+        let per_word = 64 / self.bits as usize;
+        let shift = (offset % per_word) as u32 * self.bits;
+        let word = &mut self.data[offset / per_word];
+        *word = *word & !(((1 << self.bits) - 1) << shift) | (index as u64) << shift;
+    }
+
+    /// Repacks every index with a new number of bits. Called when the palette outgrows
+    /// the current width, so the palette never shrinks and removed block types keep
+    /// their entry until the section is rebuilt.
+    fn resize(&mut self, bits: u32) {
+        let indices: Vec<usize> = (0..VOLUME).map(|offset| self.index(offset)).collect();
+        self.bits = bits;
+        self.data = vec![0; VOLUME.div_ceil(64 / bits as usize)];
+        for (offset, index) in indices.into_iter().enumerate() {
+            self.set_index(offset, index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    fn positions() -> impl Iterator<Item = (usize, usize, usize)> {
+        (0..SECTION_SIZE).flat_map(|y| (0..SECTION_SIZE).flat_map(move |x| (0..SECTION_SIZE).map(move |z| (x, y, z))))
+    }
+
+    #[test]
+    fn blocks_survive_every_resize() {
+        for kinds in [1, 2, 3, 4, 5, 16, 17, 100, 256] {
+            let mut rng = ChaCha8Rng::seed_from_u64(kinds as u64);
+            let mut section = Section::default();
+            let mut expected = vec![Block::AIR; VOLUME];
+            // every kind is used once before the rest are random, so the palette grows
+            // through every width on the way
+            for (i, (x, y, z)) in positions().enumerate() {
+                let block = if i < kinds { Block(i as u8) } else { Block(rng.random_range(0..kinds) as u8) };
+                section.set(x, y, z, block);
+                expected[Section::offset(x, y, z)] = block;
+            }
+            for (x, y, z) in positions() {
+                assert_eq!(section.get(x, y, z), expected[Section::offset(x, y, z)], "{} kinds", kinds);
+            }
+
+            let bits = (kinds as u32).next_power_of_two().trailing_zeros();
+            assert_eq!(section.bits, bits, "{} kinds", kinds);
+            let words = if bits == 0 { 0 } else { VOLUME.div_ceil(64 / bits as usize) };
+            assert!(section.heap_size() >= words * 8);
+            assert!(section.heap_size() < words * 8 + 2 * kinds + 64, "{} kinds", kinds);
+
+            let non_air = expected.iter().filter(|block| **block != Block::AIR).count();
+            assert_eq!(section.non_air as usize, non_air);
+        }
+    }
+
+    #[test]
+    fn common_sections_are_smaller_than_a_byte_per_block() {
+        let mut section = Section::default();
+        for (x, y, z) in positions() {
+            let block = match (x + y + z) % 3 {
+                0 => Block::STONE,
+                1 => Block::DIRT,
+                _ => Block::COAL_ORE,
+            };
+            section.set(x, y, z, block);
+        }
+        assert_eq!(section.bits, 2);
+        assert!(section.heap_size() <= VOLUME / 4 + 64);
+    }
+
+    #[test]
+    fn clearing_every_block_empties_the_section() {
+        let mut section = Section::default();
+        assert!(section.is_empty());
+        section.set(3, 4, 5, Block::STONE);
+        section.set(3, 4, 6, Block::SAND);
+        assert!(!section.is_empty());
+        section.set(3, 4, 5, Block::AIR);
+        section.set(3, 4, 6, Block::AIR);
+        assert!(section.is_empty());
+        assert_eq!(section.get(3, 4, 6), Block::AIR);
+    }
+
+    /// Compares memory and get/set speed with a plain block array, run with
+    /// `cargo test --release section_against_plain_array -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn section_against_plain_array() {
+        use std::{hint::black_box, time::Instant};
+
+        const ROUNDS: usize = 200;
+        for kinds in [1, 4, 16, 256] {
+            let mut rng = ChaCha8Rng::seed_from_u64(kinds as u64);
+            let blocks: Vec<Block> = (0..VOLUME).map(|_| Block(rng.random_range(0..kinds) as u8)).collect();
+            let mut section = Section::default();
+            let mut array = [[[Block::AIR; SECTION_SIZE]; SECTION_SIZE]; SECTION_SIZE];
+
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                for ((x, y, z), &block) in positions().zip(&blocks) {
+                    section.set(black_box(x), y, z, block);
+                }
+            }
+            let section_set = start.elapsed();
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                for ((x, y, z), &block) in positions().zip(&blocks) {
+                    array[black_box(x)][y][z] = block;
+                }
+            }
+            let array_set = start.elapsed();
+
+            let start = Instant::now();
+            let mut section_sum = 0usize;
+            for _ in 0..ROUNDS {
+                for (x, y, z) in positions() {
+                    section_sum += section.get(black_box(x), y, z).0 as usize;
+                }
+            }
+            let section_get = start.elapsed();
+            let start = Instant::now();
+            let mut array_sum = 0usize;
+            for _ in 0..ROUNDS {
+                for (x, y, z) in positions() {
+                    array_sum += black_box(&array)[x][y][z].0 as usize;
+                }
+            }
+            let array_get = start.elapsed();
+            assert_eq!(section_sum, array_sum);
+
+            let section_bytes = std::mem::size_of::<Section>() + section.heap_size();
+            let array_bytes = std::mem::size_of_val(&array);
+            let per_block = |elapsed: std::time::Duration| elapsed.as_nanos() as f64 / (ROUNDS * VOLUME) as f64;
+            println!(
+                "{:>3} kinds: section {:>5} bytes, get {:.2}ns, set {:.2}ns; array {} bytes, get {:.2}ns, set {:.2}ns",
+                kinds,
+                section_bytes,
+                per_block(section_get),
+                per_block(section_set),
+                array_bytes,
+                per_block(array_get),
+                per_block(array_set),
+            );
+            if kinds < 256 {
+                assert!(section_bytes < array_bytes, "{} kinds", kinds);
+            }
+        }
+    }
+}
@@ -4,11 +4,20 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coord: vec3<f32>,
     @location(1) distance: f32,
+    @location(2) tint: vec3<f32>,
 };
 
+// packed as described on `PackedVertex`
 struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) tex_coord: vec3<f32>,
+    @location(0) data: vec2<u32>,
+}
+
+// moves the chunk local positions to the chunk origin
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
 }
 
 struct CameraUniform {
@@ -19,14 +28,52 @@ struct CameraUniform {
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+// packed 8 bit rgb biome tint of every column of the chunk, indexed x * 16 + z
+struct ChunkTints {
+    columns: array<vec4<u32>, 64>,
+}
+
+@group(2) @binding(0)
+var<uniform> tints: ChunkTints;
+
 @vertex
 fn vs_main(
     in: VertexInput,
+    instance: InstanceInput,
 ) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let x = f32(in.data.x & 31u);
+    // fluid surfaces are lowered by eighths of a block
+    let y = f32((in.data.x >> 5u) & 511u) - f32(in.data.x >> 29u) / 8.0;
+    let z = f32((in.data.x >> 14u) & 31u);
+    let u = f32((in.data.x >> 19u) & 31u);
+    let v = f32((in.data.x >> 24u) & 31u);
+    let texture = f32(in.data.y & 65535u) / 65535.0;
+    // columns past the last one are untinted
+    let column = (in.data.y >> 16u) & 511u;
+    var tint = vec3<f32>(1.0);
+    if column < 256u {
+        let packed = tints.columns[column / 4u][column % 4u];
+        tint = vec3<f32>(
+            f32(packed & 255u),
+            f32((packed >> 8u) & 255u),
+            f32((packed >> 16u) & 255u),
+        ) / 255.0;
+    }
+
+    // storage x and z are render z and x
+    let position = (model_matrix * vec4<f32>(z, y, x, 1.0)).xyz;
+
     var out: VertexOutput;
-    out.clip_position = camera.view_proj * vec4<f32>(in.position, 1.0);
-    out.tex_coord = in.tex_coord;
-    out.distance = distance(camera.view_pos, in.position);
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.tex_coord = vec3<f32>(u, v, texture);
+    out.tint = tint;
+    out.distance = distance(camera.view_pos, position);
     return out;
 }
 
@@ -41,11 +88,17 @@ var side_s_diffuse: sampler;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var sample = textureSample(side_t_diffuse, side_s_diffuse, in.tex_coord); 
-    // grass top id
-    if in.tex_coord.z == 0.45050213 {
-        sample = sample * vec4<f32>(0.3, 1.0, 0.15, 1.0);
+    // biome tint, white for untinted faces
+    var sample = textureSample(side_t_diffuse, side_s_diffuse, in.tex_coord) * vec4<f32>(in.tint, 1.0);
+    // alpha test for cutout blocks, opaque blocks always pass
+    if sample.a < 0.5 {
+        discard;
     }
     // sample += vec4(1-pow(0.999, in.distance));
-    return sample;
+    return vec4<f32>(sample.rgb, 1.0);
+}
+
+@fragment
+fn fs_translucent(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(side_t_diffuse, side_s_diffuse, in.tex_coord) * vec4<f32>(in.tint, 1.0);
 }
\ No newline at end of file
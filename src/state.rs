@@ -1,11 +1,15 @@
-use std::sync::Arc;
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+use anyhow::Context;
 use tokio::{sync::Mutex, time::Instant};
 
-use winit::{event::{KeyEvent, WindowEvent}, window::Window};
+use winit::{event::{ElementState, KeyEvent, MouseButton, WindowEvent}, keyboard::{KeyCode, PhysicalKey}, window::Window};
 use wgpu::util::DeviceExt;
-use crate::{camera::{self, Camera, CameraController, Projection}, texture::{self, Texture, TextureManager}, world::{self, World}, Vertex};
+use cgmath::{EuclideanSpace, InnerSpace};
+use crate::{block::{Block, BlockRegistry}, interaction, player::{MovementMode, Player}, camera::{self, Camera, CameraController, Projection}, frustum::Frustum, level::CameraPose, mesher::{self, MeshingMode}, texture::{self, Texture, TextureManager}, streaming::{self, Focus, MeshUpdate}, world::{self, ChunkMesh, World}, InstanceRaw, PackedVertex};
+use tokio::sync::{mpsc::{self, UnboundedReceiver, UnboundedSender}, watch};
 use tokio::task::spawn;
 use tokio::task::JoinHandle;
+use crate::time::TICK;
 
 pub struct State {
     surface: wgpu::Surface,
@@ -15,7 +19,10 @@ pub struct State {
     pub config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
     render_pipeline: wgpu::RenderPipeline,
+    translucent_pipeline: wgpu::RenderPipeline,
     bind_groups: [wgpu::BindGroup; 2],
+    /// layout of the tint palette every chunk mesh binds as group 2
+    tint_bind_group_layout: Arc<wgpu::BindGroupLayout>,
     camera: Camera,
     camera_uniform: camera::CameraUniform,
     camera_buffer: wgpu::Buffer,
@@ -24,16 +31,32 @@ pub struct State {
     pub time: crate::time::Time,
     projection: Projection,
     texture_manager: Arc<TextureManager>,
-    buffers: [Arc<Mutex<Vec<(Arc<wgpu::Buffer>, usize)>>>; 2],
-    active_buffer: usize,
-    chunk_generation_handle: Option<JoinHandle<()>>,
+    block_registry: Arc<BlockRegistry>,
+    /// section meshes of the chunks in view
+    chunk_meshes: HashMap<(i64, i64), Vec<ChunkMesh>>,
+    mesh_updates: UnboundedReceiver<MeshUpdate>,
+    mesh_update_sender: UnboundedSender<MeshUpdate>,
+    /// indices of `MAX_QUADS` quads, shared by every section mesh
+    quad_index_buffer: wgpu::Buffer,
+    /// camera focus the chunk stream works towards
+    focus: watch::Sender<Focus>,
+    remesh_handle: Option<JoinHandle<()>>,
     depth_texture: texture::Texture,
-    current_base_chunk: (f32, f32)
+    render_distance: u32,
+    /// mouse buttons pressed since the last update
+    clicks: Vec<MouseButton>,
+    /// blocks the number keys select, `None` where the registry lacks the block
+    hotbar: [Option<Block>; 9],
+    selected_block: usize,
+    player: Player,
+    /// camera position before the last tick, frames are drawn between it and the
+    /// current position
+    previous_position: cgmath::Point3<f32>,
 }
 
 impl State {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: Window) -> State {
+    pub async fn new(window: Window) -> anyhow::Result<State> {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -128,30 +151,33 @@ impl State {
                 }],
                 label: Some("camera_bind_group_layout"),
             });
+        let tint_bind_group_layout = Arc::new(ChunkMesh::tint_layout(&device));
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+                bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout, &tint_bind_group_layout],
                 push_constant_ranges: &[],
             });
 
         
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
+        // opaque and cutout faces are drawn first, translucent faces are blended on top
+        // without writing depth so the ones further back stay visible
+        let create_pipeline = |label: &str, fragment_entry: &str, blend: wgpu::BlendState, translucent: bool| device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: "vs_main",
-                buffers: &[Vertex::desc(), ],
+                buffers: &[PackedVertex::desc(), InstanceRaw::desc()],
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: "fs_main",
+                entry_point: fragment_entry,
                 targets: &[Some(wgpu::ColorTargetState {
                     format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    blend: Some(blend),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
@@ -159,7 +185,8 @@ impl State {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
+                // the surface of water is also seen from below
+                cull_mode: if translucent { None } else { Some(wgpu::Face::Back) },
                 // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
                 polygon_mode: wgpu::PolygonMode::Fill,
                 // Requires Features::DEPTH_CLIP_CONTROL
@@ -169,7 +196,7 @@ impl State {
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
+                depth_write_enabled: !translucent,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState {
@@ -185,9 +212,14 @@ impl State {
             },
             multiview: None,
         });
+        let render_pipeline = create_pipeline("Render Pipeline", "fs_main", wgpu::BlendState::REPLACE, false);
+        let translucent_pipeline = create_pipeline("Translucent Render Pipeline", "fs_translucent", wgpu::BlendState::ALPHA_BLENDING, true);
         
         
         let texture_manager = TextureManager::new(&device, &queue);
+        let block_registry = Arc::new(
+            BlockRegistry::load("./assets/blocks.toml", &texture_manager).context("could not load block definitions")?,
+        );
         let texture_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor { 
             label: Some("texture bind group"),
             layout: &texture_bind_group_layout, 
@@ -203,7 +235,26 @@ impl State {
                 ],
             });
             
-        let camera = Camera::new((0.0, 100.0, 2000.0), cgmath::Deg(90.0), cgmath::Deg(-20.0));
+        let mut world = match World::open("./saves/world", "seed".to_string(), 20) {
+            Ok(world) => world,
+            Err(e) => {
+                log::error!("could not open world save, running without saving: {:#}", e);
+                World::new("seed".to_string(), 20)
+            }
+        };
+        world.meshing_mode = MeshingMode::from_env();
+        if let Ok(max_chunks) = std::env::var(world::MAX_CHUNKS_VAR) {
+            match max_chunks.parse() {
+                Ok(max_chunks) => world.max_chunks = max_chunks,
+                Err(_) => log::warn!(
+                    "invalid {} \"{}\", keeping the limit of {} chunks",
+                    world::MAX_CHUNKS_VAR,
+                    max_chunks,
+                    world.max_chunks
+                ),
+            }
+        }
+        let camera = world.camera.to_camera();
         let camera_controller = camera::CameraController::new(8.0, 0.8);
         let projection = Projection::new(size.width, size.height, cgmath::Deg(40.), 0.1, 100.0);
         let mut camera_uniform = camera::CameraUniform::new();
@@ -224,21 +275,31 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        let world = Arc::new(Mutex::new(world::World::new("seed".to_string(), 20)));
-        
+        let render_distance = world.render_distance;
+        let hotbar = interaction::HOTBAR.map(|name| block_registry.by_name(name));
+        let world = Arc::new(Mutex::new(world));
+
         let texture_manager = Arc::new(texture_manager);
-        let active_buffer = 0;
-        let buffers = [Arc::new(Mutex::new(vec![])), Arc::new(Mutex::new(vec![]))];
-        World::generate_mesh(
-            texture_manager.clone(), 
+        let (mesh_update_sender, mesh_updates) = mpsc::unbounded_channel();
+        let (focus, focus_receiver) = watch::channel(Self::focus_of(&camera));
+        spawn(streaming::stream_chunks(
+            block_registry.clone(),
             device.clone(),
-            world.clone(), 
-            (camera.position.x, camera.position.z),
-            buffers[0].clone()).await;
+            tint_bind_group_layout.clone(),
+            world.clone(),
+            focus_receiver,
+            mesh_update_sender.clone(),
+        ));
+        let quad_indices: Vec<u32> = (0..mesher::MAX_QUADS as u32).flat_map(mesher::quad_indices).collect();
+        let quad_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Quad Index Buffer"),
+            contents: bytemuck::cast_slice(&quad_indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
         let time = crate::time::Time::new();
         let depth_texture = Texture::create_depth_texture(&device, &config, "Depth Texture");
 
-        Self {
+        Ok(Self {
             surface,
             window,
             device,
@@ -246,7 +307,10 @@ impl State {
             config,
             size,
             render_pipeline,
+            translucent_pipeline,
             bind_groups: [texture_bind_group, camera_bind_group],
+            tint_bind_group_layout,
+            previous_position: camera.position,
             camera,
             camera_buffer,
             camera_uniform,
@@ -255,12 +319,20 @@ impl State {
             time,
             projection,
             texture_manager,
-            buffers,
-            active_buffer,
-            chunk_generation_handle: None,
+            block_registry,
+            chunk_meshes: HashMap::new(),
+            mesh_updates,
+            mesh_update_sender,
+            quad_index_buffer,
+            focus,
+            remesh_handle: None,
             depth_texture,
-            current_base_chunk: (0.0, 0.0)
-        }
+            render_distance,
+            clicks: vec![],
+            hotbar,
+            selected_block: 0,
+            player: Player::default(),
+        })
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -280,24 +352,121 @@ impl State {
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
-            WindowEvent::KeyboardInput { event, .. } => {
-                match event {
-                    KeyEvent { physical_key, state, .. } => {
-                        self.camera_controller.process_keyboard(*physical_key, *state)
+            WindowEvent::KeyboardInput { event: KeyEvent { physical_key, state, repeat, .. }, .. } => {
+                if let (PhysicalKey::Code(code), ElementState::Pressed) = (physical_key, state) {
+                    if *code == KeyCode::KeyF {
+                        if !repeat {
+                            self.player.toggle_mode();
+                            log::info!("movement mode: {:?}", self.player.mode);
+                        }
+                        return true;
+                    }
+                    if let Some(slot) = Self::hotbar_slot(*code) {
+                        self.selected_block = slot;
+                        if let Some(block) = self.hotbar[slot] {
+                            log::info!("selected {}", self.block_registry.get(block).name);
+                        }
+                        return true;
                     }
                 }
+                self.camera_controller.process_keyboard(*physical_key, *state)
+            }
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: button @ (MouseButton::Left | MouseButton::Right), .. } => {
+                self.clicks.push(*button);
+                true
             }
             _ => false
         }
     }
 
+    fn hotbar_slot(code: KeyCode) -> Option<usize> {
+        let slot = match code {
+            KeyCode::Digit1 => 0,
+            KeyCode::Digit2 => 1,
+            KeyCode::Digit3 => 2,
+            KeyCode::Digit4 => 3,
+            KeyCode::Digit5 => 4,
+            KeyCode::Digit6 => 5,
+            KeyCode::Digit7 => 6,
+            KeyCode::Digit8 => 7,
+            KeyCode::Digit9 => 8,
+            _ => return None,
+        };
+        Some(slot)
+    }
+
+    /// Breaks or places blocks for the mouse buttons pressed since the last update.
+    async fn handle_clicks(&mut self) {
+        if self.clicks.is_empty() {
+            return;
+        }
+        let mut world = self.world.lock().await;
+        for button in std::mem::take(&mut self.clicks) {
+            match button {
+                MouseButton::Left => {
+                    if let Some(block) = interaction::break_block(&mut world, &self.block_registry, &self.camera) {
+                        log::info!("broke {}", self.block_registry.get(block).name);
+                    }
+                }
+                _ => {
+                    if let Some(block) = self.hotbar[self.selected_block] {
+                        interaction::place_block(&mut world, &self.block_registry, &self.camera, block);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn move_camera(&mut self) {
+        let dt = TICK.as_secs_f32();
+        match self.player.mode {
+            MovementMode::Fly => self.camera_controller.update_camera(&mut self.camera, dt),
+            MovementMode::Walk => {
+                let world = self.world.lock().await;
+                let registry = &self.block_registry;
+                // chunks that are not loaded yet hold the player up rather than let it fall
+                let solid = |position| world.loaded_block(position).is_none_or(|block| registry.get(block).solid);
+                self.camera_controller.update_walking(&mut self.camera, &mut self.player, dt, solid);
+            }
+        }
+    }
+
+    /// Advances the simulation by one `TICK`. Anything that should run at the same
+    /// rate whatever the frame rate, like movement and block updates, goes here.
+    async fn tick(&mut self) {
+        self.previous_position = self.camera.position;
+        self.move_camera().await;
+        self.handle_clicks().await;
+        self.world.lock().await.tick_blocks(&self.block_registry, self.time.tick);
+        self.time.finish_tick();
+    }
+
+    /// The camera as drawn, part way between its positions before and after the last
+    /// tick.
+    fn view_camera(&self) -> Camera {
+        let mut camera = self.camera.clone();
+        camera.position = self.previous_position + (self.camera.position - self.previous_position) * self.time.alpha();
+        camera
+    }
+
+    /// Writes modified chunks to disk.
+    pub async fn save(&mut self) {
+        let mut world = self.world.lock().await;
+        world.camera = CameraPose::from(&self.camera);
+        if let Err(e) = world.save() {
+            log::error!("could not save world: {:#}", e);
+        }
+    }
+
     pub async fn update(&mut self) {
         log::info!("update");
-        log::info!("total chunks created: {}", self.world.lock().await.chunks.len());
         self.time.set_update_start_time();
 
-        self.camera_controller.update_camera(&mut self.camera, self.time.delta_time());
-        self.camera_uniform.update_view_proj(&self.camera, &self.projection);
+        for _ in 0..self.time.advance() {
+            self.tick().await;
+        }
+        self.camera_controller.rotate_camera(&mut self.camera, self.time.delta_time());
+        self.camera_uniform.update_view_proj(&self.view_camera(), &self.projection);
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
@@ -321,7 +490,6 @@ impl State {
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
-        let active_buffer = self.buffers[self.active_buffer].lock().await;
 
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -352,11 +520,57 @@ impl State {
             render_pass.set_pipeline(&self.render_pipeline);
             render_pass.set_bind_group(0, &self.bind_groups[0], &[]);
             render_pass.set_bind_group(1, &self.bind_groups[1], &[]);
-            for (buffer, num) in active_buffer.iter() {
+            render_pass.set_index_buffer(self.quad_index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+            // skip the sections outside the view
+            let view_camera = self.view_camera();
+            let frustum = Frustum::from_matrix(self.projection.calc_matrix() * view_camera.calc_matrix());
+            let visible: Vec<_> = self
+                .chunk_meshes
+                .values()
+                .flatten()
+                .filter(|mesh| frustum.intersects(&mesh.bounds))
+                .collect();
+            let sections = self.chunk_meshes.values().map(Vec::len).sum::<usize>();
+            let chunks_drawn = visible.iter().map(|mesh| mesh.chunk).collect::<HashSet<_>>().len();
+            log::info!(
+                "drawn {} chunks and culled {}, drawn {} sections and culled {}",
+                chunks_drawn,
+                self.chunk_meshes.len() - chunks_drawn,
+                visible.len(),
+                sections - visible.len()
+            );
+
+            for mesh in visible.iter() {
+                let (buffer, quads) = &mesh.opaque;
+                render_pass.set_bind_group(2, &mesh.tints, &[]);
                 render_pass.set_vertex_buffer(0, buffer.slice(..));
-                render_pass.draw(0..*num as u32, 0..1);
+                render_pass.set_vertex_buffer(1, mesh.instance.slice(..));
+                render_pass.draw_indexed(0..*quads as u32 * 6, 0, 0..1);
             }
-            log::info!("vertex count: {}", active_buffer.iter().map(|x| x.1).sum::<usize>());
+
+            // blending only looks right if the chunks furthest away are drawn first
+            let distance = |mesh: &ChunkMesh| (mesh.bounds.center() - view_camera.position.to_vec()).magnitude2();
+            let mut translucent: Vec<_> = visible
+                .iter()
+                .filter(|mesh| mesh.translucent.is_some())
+                .collect();
+            translucent.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+            render_pass.set_pipeline(&self.translucent_pipeline);
+            for mesh in translucent {
+                let (buffer, quads) = mesh.translucent.as_ref().unwrap();
+                render_pass.set_bind_group(2, &mesh.tints, &[]);
+                render_pass.set_vertex_buffer(0, buffer.slice(..));
+                render_pass.set_vertex_buffer(1, mesh.instance.slice(..));
+                render_pass.draw_indexed(0..*quads as u32 * 6, 0, 0..1);
+            }
+            log::info!(
+                "vertex count: {}",
+                visible
+                    .iter()
+                    .map(|mesh| 4 * (mesh.opaque.1 + mesh.translucent.as_ref().map_or(0, |buffer| buffer.1)))
+                    .sum::<usize>()
+            );
         }
         // submit will accept anything that implements IntoIter
         self.queue.submit(std::iter::once(encoder.finish()));
@@ -370,52 +584,67 @@ impl State {
     async fn update_mesh(&mut self) {
         let start = Instant::now();
         log::info!("mesh update");
-        let (camera_x, camera_z) = ((self.camera.position.x / 16.0).floor(), (self.camera.position.z / 16.0).floor());
+        // let the chunk stream know when the camera enters another chunk or turns, and
+        // stop showing the chunks it leaves behind
+        let latest = Self::focus_of(&self.camera);
+        let render_distance = self.render_distance;
+        let chunk_meshes = &mut self.chunk_meshes;
+        self.focus.send_if_modified(|focus| {
+            if !latest.needs_reorder(focus) {
+                return false;
+            }
+            if latest.chunk() != focus.chunk() {
+                log::info!("streaming chunks around {:?}", latest.chunk());
+                chunk_meshes.retain(|position, _| streaming::in_range(*position, latest.center, render_distance));
+            }
+            *focus = latest;
+            true
+        });
 
-        let handle = std::mem::take(&mut self.chunk_generation_handle);
-        let inactive_buffer = match self.active_buffer {
-            0 => 1,
-            _ => 0
-        };
-        match handle {
-            Some(handle) => {
-                log::info!("there is a handle");
-                if handle.is_finished() {
-                    
-                    let join_time = Instant::now();
-                    log::info!("mesh finished");
-                    // let buffers = handle.await;
-                    // match buffers {
-                    //     Ok(buffers) => self.buffers[inactive_buffer] = buffers,
-                    //     Err(e) => {
-                    //         log::error!("{}", e);
-                    //     }
-                    // }
-                    self.chunk_generation_handle = None;
-                    self.active_buffer = inactive_buffer;
-                    log::info!("join time: {}ms", join_time.elapsed().as_secs_f32() * 1000.0);
-                }
-                else {
-                    log::info!("waiting on new mesh");
-                    std::mem::swap(&mut self.chunk_generation_handle, &mut Some(handle));
+        // show the chunks finished since the last frame
+        let mut received = 0;
+        while let Ok(update) = self.mesh_updates.try_recv() {
+            self.apply_mesh_update(update);
+            received += 1;
+        }
+        log::info!("received {} mesh updates", received);
+
+        // rebuild the meshes changed blocks made stale, without waiting on a running
+        // stream that holds the world lock
+        if self.remesh_handle.as_ref().is_none_or(|handle| handle.is_finished()) {
+            let has_dirty_chunks = self.world.try_lock().is_ok_and(|world| world.has_dirty_chunks());
+            self.remesh_handle = has_dirty_chunks.then(|| spawn(World::remesh_dirty(
+                self.block_registry.clone(),
+                self.device.clone(),
+                self.tint_bind_group_layout.clone(),
+                self.world.clone(),
+                self.mesh_update_sender.clone(),
+            )));
+        }
+        log::info!("mesh update time: {}", start.elapsed().as_secs_f32() * 1000.0);
+    }
+
+    fn focus_of(camera: &Camera) -> Focus {
+        Focus {
+            center: (camera.position.x / 16.0, camera.position.z / 16.0),
+            forward: (camera.yaw().0.cos(), camera.yaw().0.sin()),
+        }
+    }
+
+    fn apply_mesh_update(&mut self, update: MeshUpdate) {
+        match update {
+            MeshUpdate::Chunk { chunk, meshes } => {
+                // the camera may have moved on while the chunk was built
+                if streaming::in_range(chunk, self.focus.borrow().center, self.render_distance) {
+                    self.chunk_meshes.insert(chunk, meshes);
                 }
             }
-            None => {
-                log::info!("test if new mesh is needed");
-                if self.current_base_chunk != (camera_x, camera_z).into() {
-                    self.current_base_chunk = (camera_x, camera_z).into();
-                    self.chunk_generation_handle = Some(spawn(
-                        World::generate_mesh(
-                            self.texture_manager.clone(), 
-                            self.device.clone(), 
-                            self.world.clone(),
-                            (self.camera.position.x, self.camera.position.z),
-                            self.buffers[inactive_buffer].clone()
-                    )));
+            MeshUpdate::Section { chunk, section, mesh } => {
+                if let Some(meshes) = self.chunk_meshes.get_mut(&chunk) {
+                    meshes.retain(|shown| shown.section != section);
+                    meshes.extend(mesh);
                 }
-
             }
         }
-        log::info!("mesh update time: {}", start.elapsed().as_secs_f32() * 1000.0);
     }
 }
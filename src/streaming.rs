@@ -0,0 +1,410 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashSet},
+    sync::Arc,
+};
+
+use tokio::{
+    sync::{
+        mpsc::{self, UnboundedSender},
+        watch, Mutex,
+    },
+    task::spawn_blocking,
+    time::Instant,
+};
+
+use crate::{
+    block::BlockRegistry,
+    decoration::Placement,
+    generation::{self, NoiseContext},
+    mesher::MeshingMode,
+    region::RegionStorage,
+    section::SECTIONS,
+    world::{self, Chunk, ChunkMesh, MeshSnapshot, World},
+};
+
+/// Most loading and meshing tasks running at once, so the main loop still finds a
+/// free worker.
+pub const WORKER_TASKS: usize = 6;
+
+/// Meshes sent to the renderer as they are built.
+#[derive(Debug)]
+pub enum MeshUpdate {
+    /// every section mesh of a chunk that came into view
+    Chunk { chunk: (i64, i64), meshes: Vec<ChunkMesh> },
+    /// a rebuilt section, `None` when it has no visible faces any more
+    Section { chunk: (i64, i64), section: usize, mesh: Option<ChunkMesh> },
+}
+
+/// Results of worker tasks, handled by the task streaming the chunks.
+enum Work {
+    Loaded((i64, i64), Box<Chunk>),
+    Generated((i64, i64), Box<Chunk>, Vec<Placement>),
+    Meshed((i64, i64), Box<MeshSnapshot>, Box<[Option<ChunkMesh>; SECTIONS]>),
+}
+
+/// Whether a chunk is within the render distance of `center`, the camera position in
+/// chunks with render x first.
+pub fn in_range(position: (i64, i64), center: (f32, f32), render_distance: u32) -> bool {
+    (position.1 as f32 - center.0).powi(2) + (position.0 as f32 - center.1).powi(2) <= (render_distance as f32).powi(2)
+}
+
+/// Chunks within the render distance of `center`.
+pub fn chunks_in_range(center: (f32, f32), render_distance: u32) -> Vec<(i64, i64)> {
+    let radius = render_distance as i64 + 1;
+    let (base_x, base_z) = (center.0 as i64, center.1 as i64);
+    let mut chunks = vec![];
+    for i in base_x - radius..=base_x + radius {
+        for j in base_z - radius..=base_z + radius {
+            if in_range((j, i), center, render_distance) {
+                chunks.push((j, i));
+            }
+        }
+    }
+    chunks
+}
+
+/// Where the camera is and which way it looks, in chunks and render axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Focus {
+    /// camera position divided by the chunk size, render x first
+    pub center: (f32, f32),
+    /// horizontal view direction, normalised
+    pub forward: (f32, f32),
+}
+
+/// Turning the camera further than this, as the cosine of the angle, reorders the
+/// queues even if the camera stays in the same chunk.
+const REORDER_COSINE: f32 = 0.966;
+
+impl Focus {
+    pub fn chunk(&self) -> (f32, f32) {
+        (self.center.0.floor(), self.center.1.floor())
+    }
+
+    /// Lower is sooner: the distance to the chunk, up to three times as far for chunks
+    /// behind the camera as for chunks straight ahead.
+    pub fn priority(&self, position: (i64, i64)) -> f32 {
+        let offset = (position.1 as f32 + 0.5 - self.center.0, position.0 as f32 + 0.5 - self.center.1);
+        let distance = (offset.0 * offset.0 + offset.1 * offset.1).sqrt();
+        if distance < 1.0 {
+            return distance;
+        }
+        let facing = (offset.0 * self.forward.0 + offset.1 * self.forward.1) / distance;
+        distance * (2.0 - facing)
+    }
+
+    /// Whether the camera moved to another chunk or turned enough to reorder the queues.
+    pub fn needs_reorder(&self, other: &Focus) -> bool {
+        self.chunk() != other.chunk()
+            || self.forward.0 * other.forward.0 + self.forward.1 * other.forward.1 < REORDER_COSINE
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Queued {
+    priority: f32,
+    position: (i64, i64),
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    // reversed so the heap pops the lowest priority value first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority)
+    }
+}
+
+/// Chunks waiting for a worker, closest to the camera and most in view first.
+#[derive(Debug, Default)]
+pub struct ChunkQueue {
+    heap: BinaryHeap<Queued>,
+}
+
+impl ChunkQueue {
+    pub fn push(&mut self, position: (i64, i64), focus: &Focus) {
+        self.heap.push(Queued { priority: focus.priority(position), position });
+    }
+
+    pub fn pop(&mut self) -> Option<(i64, i64)> {
+        self.heap.pop().map(|queued| queued.position)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Recomputes every priority for a new focus and returns the chunks `keep` rejects,
+    /// which are no longer queued.
+    pub fn reorder(&mut self, focus: &Focus, keep: impl Fn((i64, i64)) -> bool) -> Vec<(i64, i64)> {
+        let (kept, dropped): (Vec<_>, Vec<_>) = self.heap.drain().map(|queued| queued.position).partition(|position| keep(*position));
+        for position in kept {
+            self.push(position, focus);
+        }
+        dropped
+    }
+}
+
+/// State of the chunk streaming task.
+struct Streamer {
+    registry: Arc<BlockRegistry>,
+    device: Arc<wgpu::Device>,
+    tint_layout: Arc<wgpu::BindGroupLayout>,
+    updates: UnboundedSender<MeshUpdate>,
+    work: UnboundedSender<Work>,
+    focus: Focus,
+    render_distance: u32,
+    meshing_mode: MeshingMode,
+    noise: Arc<NoiseContext>,
+    storage: Option<Arc<std::sync::Mutex<RegionStorage>>>,
+    /// chunks the renderer was sent and still shows
+    sent: HashSet<(i64, i64)>,
+    /// chunks in range waiting for their neighbours to be loaded
+    waiting: HashSet<(i64, i64)>,
+    to_mesh: ChunkQueue,
+    /// chunks to read from disk, or to generate if they were never saved
+    to_load: ChunkQueue,
+    /// chunks queued or handed to a worker, so they are not queued twice
+    meshing: HashSet<(i64, i64)>,
+    loading: HashSet<(i64, i64)>,
+    running: usize,
+    /// whether chunks out of range were unloaded since the queues last ran dry
+    unloaded: bool,
+}
+
+impl Streamer {
+    /// Works out what the focus needs: chunks meshed already are sent right away, the
+    /// rest are queued for meshing or wait for their neighbours to be loaded or
+    /// generated. Queued chunks that are out of range now are cancelled. Nothing is
+    /// read from disk here, the world is locked.
+    fn plan(&mut self, world: &mut World, focus: Focus) {
+        let start = Instant::now();
+        let moved = focus.chunk() != self.focus.chunk();
+        self.focus = focus;
+        let (center, render_distance) = (focus.center, self.render_distance);
+        let cancelled_meshes = self.to_mesh.reorder(&focus, |position| in_range(position, center, render_distance));
+        for position in cancelled_meshes.iter() {
+            self.meshing.remove(position);
+        }
+        let cancelled_chunks = self.to_load.reorder(&focus, |position| in_range(position, center, render_distance + 1));
+        for position in cancelled_chunks.iter() {
+            self.loading.remove(position);
+        }
+        if !moved {
+            return;
+        }
+        self.sent.retain(|position| in_range(*position, center, render_distance));
+        self.waiting.clear();
+        for position in chunks_in_range(center, render_distance) {
+            if self.sent.contains(&position) || self.meshing.contains(&position) {
+                continue;
+            }
+            if let Some(meshes) = world.meshes_of(position) {
+                let _ = self.updates.send(MeshUpdate::Chunk { chunk: position, meshes });
+                self.sent.insert(position);
+                continue;
+            }
+            for neighbour in world::neighbourhood(position) {
+                if !self.loading.contains(&neighbour) && !world.is_loaded(neighbour) {
+                    self.loading.insert(neighbour);
+                    self.to_load.push(neighbour, &focus);
+                }
+            }
+            self.waiting.insert(position);
+        }
+        let candidates: Vec<_> = self.waiting.iter().copied().collect();
+        self.take_ready(world, &candidates);
+        self.unloaded = false;
+        log::info!(
+            "planned chunks around {:?}: {} to mesh, {} to load, {} waiting, cancelled {} meshes and {} chunks in {}ms",
+            focus.chunk(),
+            self.to_mesh.len(),
+            self.to_load.len(),
+            self.waiting.len(),
+            cancelled_meshes.len(),
+            cancelled_chunks.len(),
+            start.elapsed().as_secs_f32() * 1000.0
+        );
+    }
+
+    /// Queues the waiting candidates whose neighbours are all in memory for meshing.
+    fn take_ready(&mut self, world: &World, candidates: &[(i64, i64)]) {
+        for candidate in candidates {
+            if self.waiting.contains(candidate) && world.neighbourhood_loaded(*candidate) {
+                self.waiting.remove(candidate);
+                self.meshing.insert(*candidate);
+                self.to_mesh.push(*candidate, &self.focus);
+            }
+        }
+    }
+
+    /// Hands queued chunks to worker tasks, meshing before loading.
+    fn dispatch(&mut self, world: &mut World) {
+        while self.running < WORKER_TASKS {
+            let work = self.work.clone();
+            if let Some(position) = self.to_mesh.pop() {
+                let Some(snapshot) = world.mesh_snapshot(position) else {
+                    self.meshing.remove(&position);
+                    continue;
+                };
+                let (registry, device, mode) = (self.registry.clone(), self.device.clone(), self.meshing_mode);
+                let tint_layout = self.tint_layout.clone();
+                spawn_blocking(move || {
+                    let meshes = snapshot.build(&registry, &device, &tint_layout, mode);
+                    let _ = work.send(Work::Meshed(position, Box::new(snapshot), Box::new(meshes)));
+                });
+            } else if let Some(position) = self.to_load.pop() {
+                if world.is_loaded(position) {
+                    self.loading.remove(&position);
+                    continue;
+                }
+                let (noise, storage) = (self.noise.clone(), self.storage.clone());
+                spawn_blocking(move || {
+                    let saved = storage.and_then(|storage| world::read_chunk(&storage, &noise, position));
+                    let _ = work.send(match saved {
+                        Some(chunk) => Work::Loaded(position, Box::new(chunk)),
+                        None => {
+                            let (chunk, placements) = generation::generate_decorated(&noise, position);
+                            Work::Generated(position, Box::new(chunk), placements)
+                        }
+                    });
+                });
+            } else {
+                break;
+            }
+            self.running += 1;
+        }
+    }
+
+    fn finish(&mut self, world: &mut World, work: Work) {
+        self.running -= 1;
+        match work {
+            Work::Loaded(position, chunk) => {
+                self.loading.remove(&position);
+                world.insert_loaded(position, *chunk);
+                self.take_ready(world, &world::neighbourhood(position));
+            }
+            Work::Generated(position, chunk, placements) => {
+                self.loading.remove(&position);
+                world.insert_generated(position, *chunk, placements);
+                self.take_ready(world, &world::neighbourhood(position));
+            }
+            Work::Meshed(position, snapshot, meshes) => {
+                self.meshing.remove(&position);
+                let meshes = world.store_meshes(position, &snapshot, *meshes);
+                // chunks that went out of range are kept meshed for when they come back
+                if let Some(meshes) = meshes.filter(|_| in_range(position, self.focus.center, self.render_distance)) {
+                    let _ = self.updates.send(MeshUpdate::Chunk { chunk: position, meshes });
+                    self.sent.insert(position);
+                }
+            }
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.running == 0 && self.to_mesh.is_empty() && self.to_load.is_empty()
+    }
+}
+
+/// Loads, generates and meshes the chunks in render distance of the camera for as long
+/// as `focus` has a sender, sending each chunk to the renderer as soon as its meshes
+/// are ready.
+///
+/// Reading chunks from disk, terrain generation and meshing run on up to
+/// [`WORKER_TASKS`] blocking worker tasks, taking the chunks closest to the camera and
+/// in view first. The world is only locked to hand out work and to take results back:
+/// loaded and generated chunks are inserted with their decorations, and a chunk is
+/// meshed from a [`MeshSnapshot`] once it and its four neighbours are in memory.
+/// Meshing is preferred over loading so chunks show up while the rest of the ring is
+/// still being loaded. When the camera moves the
+/// queues are reordered and chunks that left the range are dropped from them, work
+/// already handed to a worker still finishes.
+///
+/// The renderer is expected to drop chunks outside the range of the focus it sends.
+pub async fn stream_chunks(
+    registry: Arc<BlockRegistry>,
+    device: Arc<wgpu::Device>,
+    tint_layout: Arc<wgpu::BindGroupLayout>,
+    world: Arc<Mutex<World>>,
+    mut focus: watch::Receiver<Focus>,
+    updates: UnboundedSender<MeshUpdate>,
+) {
+    let (work_tx, mut work_rx) = mpsc::unbounded_channel();
+    let mut world_lock = world.lock().await;
+    let first = *focus.borrow_and_update();
+    let mut streamer = Streamer {
+        registry,
+        device,
+        tint_layout,
+        updates,
+        work: work_tx,
+        // differs from any chunk so the first plan looks at every chunk
+        focus: Focus { center: (f32::NAN, f32::NAN), ..first },
+        render_distance: world_lock.render_distance,
+        meshing_mode: world_lock.meshing_mode,
+        noise: world_lock.noise(),
+        storage: world_lock.storage(),
+        sent: HashSet::new(),
+        waiting: HashSet::new(),
+        to_mesh: ChunkQueue::default(),
+        to_load: ChunkQueue::default(),
+        meshing: HashSet::new(),
+        loading: HashSet::new(),
+        running: 0,
+        unloaded: false,
+    };
+    streamer.plan(&mut world_lock, first);
+
+    loop {
+        streamer.dispatch(&mut world_lock);
+        if streamer.is_idle() && !streamer.unloaded {
+            if let Err(e) = world_lock.unload_chunks(streamer.focus.center) {
+                log::error!("could not save unloaded chunks: {:#}", e);
+            }
+            log::info!("buffers created: {}", world_lock.count_buffers());
+            streamer.unloaded = true;
+        }
+        drop(world_lock);
+
+        let work = if streamer.running == 0 {
+            match focus.changed().await {
+                Ok(()) => None,
+                Err(_) => break,
+            }
+        } else {
+            tokio::select! {
+                work = work_rx.recv() => work,
+                changed = focus.changed() => match changed {
+                    Ok(()) => None,
+                    Err(_) => break,
+                },
+            }
+        };
+
+        world_lock = world.lock().await;
+        if let Some(work) = work {
+            streamer.finish(&mut world_lock, work);
+        }
+        let latest = *focus.borrow_and_update();
+        if latest.needs_reorder(&streamer.focus) {
+            streamer.plan(&mut world_lock, latest);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use crate::{
+    block::{Block, BlockRegistry},
+    world::{Chunk, World},
+};
+
+/// The blocks of `assets/blocks.toml`, every texture at layer 0.
+pub fn registry() -> BlockRegistry {
+    let text = std::fs::read_to_string("assets/blocks.toml").unwrap();
+    BlockRegistry::parse(&text, |_| Some(0.0)).unwrap()
+}
+
+/// Air with a stone floor at y 10, in the chunks up to `radius` chunks from the origin
+/// along each axis.
+pub fn floor_world(radius: i64) -> World {
+    let mut world = World::new("floor".to_string(), 2);
+    for chunk_x in -radius..=radius {
+        for chunk_z in -radius..=radius {
+            let mut chunk = Chunk::new((chunk_x as f32, chunk_z as f32));
+            for x in 0..16 {
+                for z in 0..16 {
+                    chunk.set_block(x, 10, z, Block::STONE);
+                }
+            }
+            world.chunks.insert((chunk_x, chunk_z), chunk);
+        }
+    }
+    world
+}
@@ -215,8 +215,8 @@ impl TextureManager {
         &self.block_textures
     }
 
-    pub fn get_id(&self, name: String) -> f32 {
-        *self.name_to_id.get(&name).unwrap()
+    pub fn get_id(&self, name: &str) -> Option<f32> {
+        self.name_to_id.get(name).copied()
     }
 
     fn load_textures(path: &str, device: &wgpu::Device, queue: &wgpu::Queue, label: Option<&str>) -> (Texture, HashMap<String, f32>) {
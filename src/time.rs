@@ -1,5 +1,12 @@
 use std::time::{Instant, Duration};
 
+/// Rate the simulation runs at, independent of the frame rate.
+pub const TICKS_PER_SECOND: u32 = 60;
+/// Simulated time each tick covers.
+pub const TICK: Duration = Duration::from_nanos(1_000_000_000 / TICKS_PER_SECOND as u64);
+/// Most ticks run in one frame. After a longer stall the simulation falls behind
+/// instead of spending every following frame catching up.
+pub const MAX_TICKS_PER_FRAME: u32 = 10;
 
 pub struct Time {
     frame_start_time: Instant,
@@ -8,6 +15,13 @@ pub struct Time {
     pub render_duration: Duration,
     update_duration: Duration,
     pub frame_time: Duration,
+    last_advance: Instant,
+    /// time between the last two calls to `advance`
+    delta: Duration,
+    /// time passed that the ticks have not simulated yet, less than a tick
+    accumulator: Duration,
+    /// number of ticks run so far, which is also the number of the tick running
+    pub tick: u64,
 }
 
 impl Time {
@@ -19,6 +33,10 @@ impl Time {
             render_duration: Duration::from_millis(10), 
             update_duration: Duration::from_millis(10), 
             frame_time: Duration::from_millis(10),
+            last_advance: Instant::now(),
+            delta: Duration::ZERO,
+            accumulator: Duration::ZERO,
+            tick: 0,
         }
     }
     pub fn update_frame_time(&mut self) {
@@ -38,7 +56,36 @@ impl Time {
     }
     /// elapsed time between updates in seconds
     pub fn delta_time(&self) -> f32 {
-        self.frame_time.as_secs_f32()
+        self.delta.as_secs_f32()
+    }
+    /// Adds the time passed since the last call and returns how many ticks are due.
+    /// Each of them should be run and followed by `finish_tick`.
+    pub fn advance(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = now - self.last_advance;
+        self.last_advance = now;
+        self.advance_by(elapsed)
+    }
+    /// Like `advance`, for `elapsed` passing since the last call.
+    pub fn advance_by(&mut self, elapsed: Duration) -> u32 {
+        self.delta = elapsed;
+        self.accumulator += elapsed;
+        let due = (self.accumulator.as_nanos() / TICK.as_nanos()) as u32;
+        if due > MAX_TICKS_PER_FRAME {
+            log::warn!("simulation is {} ticks behind, skipping {}", due, due - MAX_TICKS_PER_FRAME);
+            self.accumulator = Duration::ZERO;
+            return MAX_TICKS_PER_FRAME;
+        }
+        self.accumulator -= TICK * due;
+        due
+    }
+    pub fn finish_tick(&mut self) {
+        self.tick += 1;
+    }
+    /// How far the time passed is between the last tick and the next, from 0 to 1.
+    /// Rendering blends the state before and after the last tick by this much.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / TICK.as_secs_f32()
     }
     pub fn set_frame_start_time(&mut self) {
         self.frame_start_time = Instant::now()
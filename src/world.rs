@@ -1,222 +1,443 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use tokio::{sync::Mutex, time::Instant};
+use tokio::{
+    sync::{mpsc::UnboundedSender, Mutex},
+    time::Instant,
+};
 
 use crate::{
-    block::{get_block_texture_ids, Block},
-    texture::TextureManager,
-    Cardinal, Vertex,
+    biome::ChunkBiomes,
+    decoration::{PendingPlacements, Placement},
+    frustum::Aabb,
+    block::{Block, BlockRegistry},
+    block_update::BlockUpdates,
+    generation::{self, NoiseContext},
+    mesher::{self, MeshData, MeshingMode},
+    level::{CameraPose, Level, LEVEL_FORMAT_VERSION},
+    region::RegionStorage,
+    section::{Section, SECTIONS, SECTION_SIZE},
+    streaming::MeshUpdate,
+    Cardinal, Instance, PackedVertex,
 };
-use cgmath::{Vector2, Vector3};
-use noise::{NoiseFn, Perlin};
+use cgmath::{InnerSpace, Point3, Quaternion, Vector2, Vector3};
 use rand::Rng;
 use wgpu::util::DeviceExt;
 
+/// Vertex buffers and quad counts of a meshed chunk section. Translucent faces get
+/// their own buffer so they can be drawn after everything else, sorted back to front.
+#[derive(Debug, Clone)]
+pub struct ChunkMesh {
+    pub chunk: (i64, i64),
+    pub section: usize,
+    /// space the section takes up in render coordinates, for frustum culling
+    pub bounds: Aabb,
+    /// single instance moving the chunk local vertices to the chunk origin
+    pub instance: Arc<wgpu::Buffer>,
+    /// biome tints of the chunk's columns, shared by all of its sections
+    pub tints: Arc<wgpu::BindGroup>,
+    /// opaque and cutout faces
+    pub opaque: (Arc<wgpu::Buffer>, usize),
+    pub translucent: Option<(Arc<wgpu::Buffer>, usize)>,
+}
+
+impl ChunkMesh {
+    /// Layout of the tint palette bound with every mesh: the packed tint of each column
+    /// of the chunk, which vertices refer to by index.
+    pub fn tint_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("tint_bind_group_layout"),
+        })
+    }
+
+    /// Uploads the vertices built by the mesher for a section of the chunk at `position`.
+    pub fn upload(
+        device: &wgpu::Device,
+        position: Vector2<f32>,
+        section: usize,
+        tints: Arc<wgpu::BindGroup>,
+        data: &MeshData,
+    ) -> Self {
+        let create_buffer = |label: &str, vertices: &[PackedVertex]| {
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(format!("{}, x: {}, z: {}", label, position.x, position.y).as_str()),
+                contents: bytemuck::cast_slice(vertices),
+                usage: wgpu::BufferUsages::VERTEX
+            });
+            debug_assert!(vertices.len() / 4 <= mesher::MAX_QUADS);
+            (Arc::new(buffer), vertices.len() / 4)
+        };
+        // render axes are swapped relative to storage axes and y is lowered by 60
+        let origin = Instance {
+            position: Vector3::new(position.y * 16.0, -60.0, position.x * 16.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        };
+        let instance = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(format!("instance_buffer, x: {}, z: {}", position.x, position.y).as_str()),
+            contents: bytemuck::cast_slice(&[origin.to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX
+        });
+        Self {
+            chunk: (position.x as i64, position.y as i64),
+            section,
+            bounds: Aabb::new(
+                origin.position + Vector3::new(0.0, (section * SECTION_SIZE) as f32, 0.0),
+                origin.position + Vector3::new(16.0, ((section + 1) * SECTION_SIZE) as f32, 16.0),
+            ),
+            instance: Arc::new(instance),
+            tints,
+            opaque: create_buffer("vertex_buffer", &data.opaque),
+            translucent: (!data.translucent.is_empty())
+                .then(|| create_buffer("translucent_vertex_buffer", &data.translucent)),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Chunk {
-    block_data: [[[Block; 16]; 16]; 256],
+    /// sections from the bottom up, `None` for sections that are only air
+    sections: [Option<Box<Section>>; SECTIONS],
+    pub biomes: ChunkBiomes,
     position: Vector2<f32>,
-    buffer: Option<(Arc<wgpu::Buffer>, usize)>,
+    /// meshes of the sections with visible faces, `None` until the chunk is meshed
+    meshes: Option<[Option<ChunkMesh>; SECTIONS]>,
     buffers_created: u32,
+    /// set when the block data differs from what is stored on disk
+    modified: bool,
+    /// sections waiting in the remesh queue, one bit per section
+    dirty: u16,
+    /// bumped on every block change, tells whether a mesh built from a snapshot is stale
+    revision: u32,
 }
 
 impl Chunk {
     pub fn new<T: Into<Vector2<f32>>>(position: T) -> Self {
-        let block_data = [[[Block::Air; 16]; 16]; 256];
         Self {
-            block_data,
+            sections: Default::default(),
+            biomes: ChunkBiomes::default(),
             position: position.into(),
-            buffer: None,
-            buffers_created: 0
+            meshes: None,
+            buffers_created: 0,
+            modified: false,
+            dirty: 0,
+            revision: 0,
         }
     }
 
+    /// Copy of the blocks and biomes, without meshes, to mesh away from the world lock.
+    pub fn snapshot(&self) -> Self {
+        Self {
+            sections: self.sections.clone(),
+            biomes: self.biomes.clone(),
+            revision: self.revision,
+            ..Self::new(self.position)
+        }
+    }
 
-    pub fn get_side_blocks(&self, side: Cardinal) -> Box<[[Block; 16]; 256]> {
-        let mut blocks = Box::new([[Block::Air; 16]; 256]);
-        match side {
-            Cardinal::North => {
-                for (i, plane) in self.block_data.iter().enumerate() {
-                    blocks[i] = *plane.last().unwrap();
-                }
-            }
-            Cardinal::South => {
-                for (i, plane) in self.block_data.iter().enumerate() {
-                    blocks[i] = plane[0];
+    pub fn position(&self) -> Vector2<f32> {
+        self.position
+    }
+
+    pub fn get_block(&self, x: usize, y: usize, z: usize) -> Block {
+        match &self.sections[y / SECTION_SIZE] {
+            Some(section) => section.get(x, y % SECTION_SIZE, z),
+            None => Block::AIR,
+        }
+    }
+
+    pub fn set_block(&mut self, x: usize, y: usize, z: usize, block: Block) {
+        self.revision = self.revision.wrapping_add(1);
+        let slot = &mut self.sections[y / SECTION_SIZE];
+        if slot.is_none() && block == Block::AIR {
+            return;
+        }
+        let section = slot.get_or_insert_with(Default::default);
+        section.set(x, y % SECTION_SIZE, z, block);
+        if section.is_empty() {
+            *slot = None;
+        }
+    }
+
+    /// Whether a section holds anything but air.
+    pub fn has_section(&self, section: usize) -> bool {
+        self.sections[section].is_some()
+    }
+
+    /// Block data in `y, x, z` order, one byte per block.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(256 * 16 * 16);
+        for y in 0..256 {
+            for x in 0..16 {
+                for z in 0..16 {
+                    bytes.push(self.get_block(x, y, z).0);
                 }
             }
-            Cardinal::East => {
-                for (i, plane) in self.block_data.iter().enumerate() {
-                    for (j, row) in plane.iter().enumerate() {
-                        blocks[i][j] = row[row.len() - 1];
-                    }
-                }
+        }
+        bytes
+    }
+
+    pub fn from_bytes<T: Into<Vector2<f32>>>(position: T, bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut chunk = Self::new(position);
+        if bytes.len() != 256 * 16 * 16 {
+            anyhow::bail!("chunk data has {} bytes, expected {}", bytes.len(), 256 * 16 * 16);
+        }
+        for (i, byte) in bytes.iter().enumerate() {
+            chunk.set_block(i / 16 % 16, i / 256, i % 16, Block(*byte));
+        }
+        Ok(chunk)
+    }
+
+
+    /// Blocks of one border plane of the chunk, indexed by `y` and then by the
+    /// horizontal coordinate along the border.
+    pub fn get_side_blocks(&self, side: Cardinal) -> Box<[[Block; 16]; 256]> {
+        let mut blocks = Box::new([[Block::AIR; 16]; 256]);
+        for y in 0..256 {
+            if !self.has_section(y / SECTION_SIZE) {
+                continue;
             }
-            Cardinal::West => {
-                for (i, plane) in self.block_data.iter().enumerate() {
-                    for (j, row) in plane.iter().enumerate() {
-                        blocks[i][j] = row[0];
-                    }
-                }
+            for i in 0..16 {
+                blocks[y][i] = match side {
+                    Cardinal::North => self.get_block(15, y, i),
+                    Cardinal::South => self.get_block(0, y, i),
+                    Cardinal::East => self.get_block(i, y, 15),
+                    Cardinal::West => self.get_block(i, y, 0),
+                    _ => Block::AIR,
+                };
             }
-            _ => {}
         }
         blocks
     }
-    pub async fn generate_mesh(
+
+    /// Uploads the packed biome tint of every column, indexed `x * 16 + z` by the
+    /// vertices of the chunk's meshes.
+    fn tint_bind_group(&self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) -> Arc<wgpu::BindGroup> {
+        let mut tints = [0u32; 256];
+        for (x, column) in self.biomes.tints.iter().enumerate() {
+            for (z, &tint) in column.iter().enumerate() {
+                let [r, g, b] = mesher::pack_tint(tint);
+                tints[x * 16 + z] = r as u32 | (g as u32) << 8 | (b as u32) << 16;
+            }
+        }
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(format!("tint_buffer, x: {}, z: {}", self.position.x, self.position.y).as_str()),
+            contents: bytemuck::cast_slice(&tints),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        Arc::new(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("tint_bind_group"),
+        }))
+    }
+
+    /// Meshes a single section, keeping the mesh if the chunk is meshed already.
+    pub async fn generate_section_mesh(
         &mut self,
-        texture_manager: Arc<TextureManager>,
+        section: usize,
+        registry: &BlockRegistry,
         side_blocks: &[[[Block; 16]; 256]; 4],
-        device: &wgpu::Device
-    ) -> (Arc<wgpu::Buffer>, usize) {
-        // let start = std::time::Instant::now();
-
-        let mut texture_id_cache = HashMap::new();
-        let mut vertices = vec![];
-        for y in 0..self.block_data.len() {
-            for x in 0..self.block_data[y].len() {
-                for z in 0..self.block_data[y][x].len() {
-                    let current = self.block_data[y][x][z];
-                    if current == Block::Air {
-                        continue;
-                    }
-                    let mut top = Block::Air;
-                    let mut bottom = Block::Air;
-                    let north;
-                    let south;
-                    let east;
-                    let west;
-                    if y < 255 {
-                        top = self.block_data[y + 1][x][z];
-                    }
-                    if y > 0 {
-                        bottom = self.block_data[y - 1][x][z];
-                    }
-
-                    if x < 15 {
-                        north = self.block_data[y][x + 1][z];
-                    } else {
-                        north = side_blocks[0][y][z];
-                        // println!("north {:?}", side_blocks[0][y][z]);
-                    }
-
-                    if x > 0 {
-                        south = self.block_data[y][x - 1][z];
-                    } else {
-                        south = side_blocks[1][y][z];
-                        // println!("south {:?}", side_blocks[1][y][z]);
-                    }
-
-                    if z > 0 {
-                        east = self.block_data[y][x][z - 1];
-                    } else {
-                        east = side_blocks[2][y][x];
-                        // println!("east {:?}", side_blocks[2][y][x]);
-                    }
-
-                    if z < 15 {
-                        west = self.block_data[y][x][z + 1];
-                    } else {
-                        west = side_blocks[3][y][x];
-                        // println!("west {:?}", side_blocks[3][y][x]);
-                    }
-
-                    let position: Vector3<f32> = (
-                        x as f32 + self.position.x * 16.0,
-                        y as f32,
-                        z as f32 + self.position.y * 16.0,
-                    )
-                        .into();
-
-                    if let None = texture_id_cache.get(&current) {
-                        let textures = get_block_texture_ids(current, texture_manager.clone()).await;
-                        texture_id_cache.insert(current, textures);
-                    }
-                    let textures = texture_id_cache.get(&current).unwrap();
-                    match top {
-                        Block::Air => {
-                            let texture_id = textures[crate::Cardinal::Up as usize];
-                            vertices.append(&mut top_face(position, texture_id).to_vec());
-                        }
-                        _ => {}
-                    }
-                    match bottom {
-                        Block::Air => {
-                            let texture_id = textures[crate::Cardinal::Down as usize];
-                            vertices.append(&mut bottom_face(position, texture_id).to_vec());
-                        }
-                        _ => {}
-                    }
-                    match north {
-                        Block::Air => {
-                            let texture_id = textures[crate::Cardinal::North as usize];
-                            vertices.append(&mut north_face(position, texture_id).to_vec());
-                        }
-                        _ => {}
-                    }
-                    match south {
-                        Block::Air => {
-                            let texture_id = textures[crate::Cardinal::South as usize];
-                            vertices.append(&mut south_face(position, texture_id).to_vec());
-                        }
-                        _ => {}
-                    }
-                    match east {
-                        Block::Air => {
-                            let texture_id = textures[crate::Cardinal::East as usize];
-                            vertices.append(&mut east_face(position, texture_id).to_vec());
-                        }
-                        _ => {}
-                    }
-                    match west {
-                        Block::Air => {
-                            let texture_id = textures[crate::Cardinal::West as usize];
-                            vertices.append(&mut west_face(position, texture_id).to_vec());
-                        }
-                        _ => {}
-                    }
-                }
-            }
+        device: &wgpu::Device,
+        tint_layout: &wgpu::BindGroupLayout,
+        mode: MeshingMode,
+    ) -> Option<ChunkMesh> {
+        let data = mesher::mesh_section(self, section, side_blocks, registry, mode);
+        let mesh = (!data.is_empty()).then(|| {
+            self.buffers_created += 1;
+            // biomes never change, so the palette of the other sections still holds
+            let tints = self
+                .meshes
+                .iter()
+                .flatten()
+                .flatten()
+                .next()
+                .map(|mesh| mesh.tints.clone())
+                .unwrap_or_else(|| self.tint_bind_group(device, tint_layout));
+            ChunkMesh::upload(device, self.position, section, tints, &data)
+        });
+        if let Some(meshes) = self.meshes.as_mut() {
+            meshes[section] = mesh.clone();
         }
+        mesh
+    }
 
-        self.buffers_created += 1;
-        self.buffer = Some((Arc::new(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(format!("vertex_buffer, x: {}, z: {}", self.position.x, self.position.y).as_str()),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::VERTEX
-        })), vertices.len())); 
-        self.buffer.as_ref().unwrap().clone()
-        
-    }
-    pub async fn get_or_generate_mesh(&mut self, texture_manager: Arc<TextureManager>, side_blocks: &[[[Block; 16]; 256]; 4], device: &wgpu::Device) -> (Arc<wgpu::Buffer>, usize) {
-        match self.buffer.as_ref() {
-            Some((buffer, len)) => {
-                (buffer.clone(), *len)
-            }
-            None => {
-                self.generate_mesh(texture_manager, side_blocks, device).await
+    /// Meshes every section, `None` for sections without visible faces.
+    pub fn build_meshes(
+        &self,
+        registry: &BlockRegistry,
+        side_blocks: &[[[Block; 16]; 256]; 4],
+        device: &wgpu::Device,
+        tint_layout: &wgpu::BindGroupLayout,
+        mode: MeshingMode,
+    ) -> [Option<ChunkMesh>; SECTIONS] {
+        let start = std::time::Instant::now();
+        let mut meshes: [Option<ChunkMesh>; SECTIONS] = Default::default();
+        let mut tints = None;
+        let mut vertices = 0;
+        for (section, mesh) in meshes.iter_mut().enumerate() {
+            let data = mesher::mesh_section(self, section, side_blocks, registry, mode);
+            vertices += data.len();
+            if !data.is_empty() {
+                let tints = tints.get_or_insert_with(|| self.tint_bind_group(device, tint_layout)).clone();
+                *mesh = Some(ChunkMesh::upload(device, self.position, section, tints, &data));
             }
         }
+        log::info!(
+            "meshed chunk ({}, {}) with the {:?} mesher: {} vertices in {}ms",
+            self.position.x,
+            self.position.y,
+            mode,
+            vertices,
+            start.elapsed().as_secs_f32() * 1000.0
+        );
+        meshes
     }
 }
 
 impl Default for Chunk {
     fn default() -> Self {
-        Self {
-            block_data: [[[Block::default(); 16]; 16]; 256],
-            position: (0.0, 0.0).into(),
-            buffer: None,
-            buffers_created: 0
+        Self::new((0.0, 0.0))
+    }
+}
+
+/// A chunk and the borders of its neighbours, copied out of the world so the chunk can
+/// be meshed on a worker task.
+pub struct MeshSnapshot {
+    chunk: Chunk,
+    side_blocks: Box<[[[Block; 16]; 256]; 4]>,
+    /// revisions of the chunk and its neighbours when the snapshot was taken
+    revisions: [u32; 5],
+}
+
+impl MeshSnapshot {
+    pub fn build(
+        &self,
+        registry: &BlockRegistry,
+        device: &wgpu::Device,
+        tint_layout: &wgpu::BindGroupLayout,
+        mode: MeshingMode,
+    ) -> [Option<ChunkMesh>; SECTIONS] {
+        self.chunk.build_meshes(registry, &self.side_blocks, device, tint_layout, mode)
+    }
+
+    /// Whether each section meshed from this snapshot could look different with the
+    /// blocks the world holds now. A section depends on its own blocks, the layers
+    /// right above and below it and the neighbours' borders next to it.
+    fn stale_sections(&self, chunk: &Chunk, side_blocks: &[[[Block; 16]; 256]; 4]) -> [bool; SECTIONS] {
+        let mut changed = [false; 256];
+        if chunk.revision != self.revisions[0] {
+            for (y, changed) in changed.iter_mut().enumerate() {
+                *changed = (0..16).any(|x| (0..16).any(|z| chunk.get_block(x, y, z) != self.chunk.get_block(x, y, z)));
+            }
         }
+        let mut stale = [false; SECTIONS];
+        for (section, stale) in stale.iter_mut().enumerate() {
+            let layers = section * SECTION_SIZE..(section + 1) * SECTION_SIZE;
+            let around = layers.start.saturating_sub(1)..(layers.end + 1).min(256);
+            *stale = changed[around].contains(&true)
+                || (0..4).any(|side| side_blocks[side][layers.clone()] != self.side_blocks[side][layers.clone()]);
+        }
+        stale
     }
 }
 
+/// A block found by [`World::raycast`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// world block position, in the axes of [`World::set_block`]
+    pub block: [i64; 3],
+    /// face of the block the ray entered through
+    pub face: Cardinal,
+    /// distance from the ray origin to where it entered the block
+    pub distance: f32,
+}
+
+impl RayHit {
+    /// The block in front of the face that was hit, where a placed block goes.
+    pub fn adjacent(&self) -> [i64; 3] {
+        let normal = self.face.normal();
+        [0, 1, 2].map(|axis| self.block[axis] + normal[axis])
+    }
+}
+
+/// Space a block takes up in render coordinates, for a world block position in the
+/// axes of [`World::set_block`].
+pub fn block_bounds([x, y, z]: [i64; 3]) -> Aabb {
+    let min = Vector3::new(z as f32, y as f32 - 60.0, x as f32);
+    Aabb::new(min, min + Vector3::new(1.0, 1.0, 1.0))
+}
+
+/// A chunk followed by its north, south, east and west neighbours.
+pub fn neighbourhood(position: (i64, i64)) -> [(i64, i64); 5] {
+    let (x, z) = position;
+    [(x, z), (x + 1, z), (x - 1, z), (x, z + 1), (x, z - 1)]
+}
+
+/// Reads a saved chunk and works out its biomes, without touching the world so it can
+/// run on a worker task. `None` when the chunk was never saved or could not be read,
+/// either way it has to be generated.
+pub fn read_chunk(storage: &std::sync::Mutex<RegionStorage>, noise: &NoiseContext, position: (i64, i64)) -> Option<Chunk> {
+    let chunk_position = Vector2::new(position.0 as f32, position.1 as f32);
+    let data = storage.lock().unwrap().load_chunk(position);
+    match data.and_then(|data| data.map(|data| Chunk::from_bytes(chunk_position, &data)).transpose()) {
+        Ok(Some(mut chunk)) => {
+            chunk.biomes = ChunkBiomes::generate(noise, position);
+            Some(chunk)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            log::error!("could not load chunk {:?}: {:#}", position, e);
+            None
+        }
+    }
+}
+
+/// Chunks further than the render distance plus this many chunks from the camera are
+/// unloaded.
+pub const UNLOAD_MARGIN: u32 = 5;
+/// Resident chunk limit used when none is configured.
+pub const DEFAULT_MAX_CHUNKS: usize = 4096;
+/// Environment variable overriding the resident chunk limit.
+pub const MAX_CHUNKS_VAR: &str = "MAX_CHUNKS";
+
 pub struct World {
     pub chunks: HashMap<(i64, i64), Chunk>,
     seed: [u8; 32],
     seed_string: String,
+    noise: Arc<NoiseContext>,
     pub render_distance: u32,
-    buffers_created: u32
+    /// most chunks kept in memory, the furthest are unloaded first once it is exceeded
+    pub max_chunks: usize,
+    buffers_created: u32,
+    /// shared with the worker tasks that read chunks back from disk
+    storage: Option<Arc<std::sync::Mutex<RegionStorage>>>,
+    dir: Option<PathBuf>,
+    /// decoration blocks destined for chunks that are not in memory yet
+    pending_placements: PendingPlacements,
+    /// chunk sections whose mesh is out of date, oldest first
+    remesh_queue: VecDeque<((i64, i64), usize)>,
+    pub spawn: [f32; 3],
+    pub meshing_mode: MeshingMode,
+    /// last known camera pose, written to the level descriptor on save
+    pub camera: CameraPose,
+    /// blocks waiting to react to changes around them
+    pub updates: BlockUpdates,
 }
 
 impl World {
@@ -225,450 +446,581 @@ impl World {
         let mut rng = rand_seeder::SipHasher::from(seed).into_rng();
         let mut seed = [0; 32];
         rng.fill(&mut seed);
+        let spawn = [0.0, 100.0, 2000.0];
 
         Self {
             chunks: HashMap::new(),
             seed,
             seed_string,
+            noise: Arc::new(NoiseContext::new(seed)),
             render_distance,
-            buffers_created: 0
+            max_chunks: DEFAULT_MAX_CHUNKS,
+            buffers_created: 0,
+            storage: None,
+            dir: None,
+            pending_placements: PendingPlacements::default(),
+            remesh_queue: VecDeque::new(),
+            spawn,
+            meshing_mode: MeshingMode::default(),
+            camera: CameraPose {
+                position: spawn,
+                yaw: cgmath::Rad::from(cgmath::Deg(90.0)).0,
+                pitch: cgmath::Rad::from(cgmath::Deg(-20.0)).0,
+            },
+            updates: BlockUpdates::default(),
         }
     }
-    
-    pub async fn generate_mesh(
-        texture_manager: Arc<TextureManager>, 
-        device: Arc<wgpu::Device>, 
-        world: Arc<Mutex<World>>,
-        cam_pos: (f32, f32),
-        inactive_buffer: Arc<Mutex<Vec<(Arc<wgpu::Buffer>, usize)>>>
-    ) {
-        let start = Instant::now();
-        log::info!("mesh gen: waiting on world lock");
-        let mut world_lock = world.lock().await;
-        log::info!("mesh gen: got world lock");
-        let base_x = cam_pos.0 / 16.0;
-        let base_z = cam_pos.1 / 16.0;
-        let mut buffers = vec![];
-        
-        let mut side_blocks = [[[Block::Stone; 16]; 256]; 4];
-        for i in (base_x as i32 - (world_lock.render_distance + 10) as i32)..(base_x as i32 + (world_lock.render_distance + 10) as i32) {
-            for j in (base_z as i32 - (world_lock.render_distance + 10) as i32)..(base_z as i32 + (world_lock.render_distance + 10) as i32) {
-                if (i as f32 - base_x).powf(2.0) + (j as f32 - base_z).powf(2.0) > (world_lock.render_distance as f32).powf(2.0) {
-                    continue;
+
+    /// Opens a world stored in `dir`, loading chunks from its region files before
+    /// falling back to generation. `seed` and `render_distance` are only used when the
+    /// directory does not hold a world yet.
+    pub fn open<P: AsRef<Path>>(dir: P, seed: String, render_distance: u32) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let mut world = match Level::load(dir)? {
+            Some(level) => {
+                log::info!("opening world {:?} with seed \"{}\"", dir, level.seed_string);
+                let mut world = Self::new(level.seed_string, level.render_distance);
+                if world.seed != level.seed {
+                    log::warn!("seed stored in the level descriptor differs from the derived seed, using the stored one");
+                    world.seed = level.seed;
+                    world.noise = Arc::new(NoiseContext::new(level.seed));
                 }
-                if (i as f32 - base_x).powf(2.0) + (j as f32 - base_z).powf(2.0) > ((world_lock.render_distance + 5) as f32).powf(2.0) {
-                    let chunk = world_lock.get_chunk_mut(Vector2::new(j as f32, i as f32));
-                    chunk.buffer.as_ref().unwrap().0.destroy();
-                    chunk.buffer = None;
-                    
+                world.spawn = level.spawn;
+                world.camera = level.camera;
+                world
+            }
+            None => {
+                log::info!("creating world {:?} with seed \"{}\"", dir, seed);
+                Self::new(seed, render_distance)
+            }
+        };
+        world.storage = Some(Arc::new(std::sync::Mutex::new(RegionStorage::new(dir.join("region"))?)));
+        world.pending_placements = PendingPlacements::load(dir)?;
+        world.dir = Some(dir.to_path_buf());
+        world.level().save(dir)?;
+        Ok(world)
+    }
+
+    pub fn level(&self) -> Level {
+        Level {
+            version: LEVEL_FORMAT_VERSION,
+            seed_string: self.seed_string.clone(),
+            seed: self.seed,
+            spawn: self.spawn,
+            camera: self.camera,
+            render_distance: self.render_distance,
+        }
+    }
+
+    /// Writes the level descriptor and every modified chunk back to disk.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if let Some(dir) = self.dir.as_ref() {
+            self.level().save(dir)?;
+            self.pending_placements.save(dir)?;
+        }
+        let Some(storage) = self.storage.as_ref() else {
+            return Ok(());
+        };
+        let mut storage = storage.lock().unwrap();
+        let mut saved = 0;
+        for (position, chunk) in self.chunks.iter_mut().filter(|(_, chunk)| chunk.modified) {
+            storage.save_chunk(*position, &chunk.to_bytes())?;
+            chunk.modified = false;
+            saved += 1;
+        }
+        storage.flush()?;
+        log::info!("saved {} chunks", saved);
+        Ok(())
+    }
+
+    pub fn noise(&self) -> Arc<NoiseContext> {
+        self.noise.clone()
+    }
+
+    /// Region files of a world opened from disk, for reading chunks on worker tasks.
+    pub fn storage(&self) -> Option<Arc<std::sync::Mutex<RegionStorage>>> {
+        self.storage.clone()
+    }
+
+    /// Meshes of a chunk that is loaded and meshed already.
+    pub fn meshes_of(&self, position: (i64, i64)) -> Option<Vec<ChunkMesh>> {
+        let meshes = self.chunks.get(&position)?.meshes.as_ref()?;
+        Some(meshes.iter().flatten().cloned().collect())
+    }
+
+    pub fn is_loaded(&self, position: (i64, i64)) -> bool {
+        self.is_chunk_available(&position)
+    }
+
+    /// Whether a chunk and its four neighbours are all in memory.
+    pub fn neighbourhood_loaded(&self, position: (i64, i64)) -> bool {
+        neighbourhood(position).iter().all(|position| self.is_chunk_available(position))
+    }
+
+    /// Copies a chunk and the borders of its neighbours for meshing, once all of them
+    /// are in memory.
+    pub fn mesh_snapshot(&mut self, position: (i64, i64)) -> Option<MeshSnapshot> {
+        let neighbourhood = neighbourhood(position);
+        let revisions = neighbourhood.map(|position| self.chunks.get(&position).map(|chunk| chunk.revision));
+        if revisions.contains(&None) {
+            return None;
+        }
+        let mut side_blocks = Box::new([[[Block::AIR; 16]; 256]; 4]);
+        self.side_blocks(Vector2::new(position.0 as f32, position.1 as f32), &mut side_blocks);
+        Some(MeshSnapshot {
+            chunk: self.chunks[&position].snapshot(),
+            side_blocks,
+            revisions: revisions.map(Option::unwrap),
+        })
+    }
+
+    /// Keeps the meshes built from a snapshot and returns the ones to show. Sections
+    /// whose blocks changed since the snapshot was taken are queued for remeshing.
+    pub fn store_meshes(
+        &mut self,
+        position: (i64, i64),
+        snapshot: &MeshSnapshot,
+        meshes: [Option<ChunkMesh>; SECTIONS],
+    ) -> Option<Vec<ChunkMesh>> {
+        let current = neighbourhood(position).map(|position| self.chunks.get(&position).map(|chunk| chunk.revision));
+        let stale = if current == snapshot.revisions.map(Some) {
+            [false; SECTIONS]
+        } else if current.contains(&None) {
+            [true; SECTIONS]
+        } else {
+            let mut side_blocks = Box::new([[[Block::AIR; 16]; 256]; 4]);
+            self.side_blocks(Vector2::new(position.0 as f32, position.1 as f32), &mut side_blocks);
+            snapshot.stale_sections(&self.chunks[&position], &side_blocks)
+        };
+        let chunk = self.chunks.get_mut(&position)?;
+        chunk.buffers_created += meshes.iter().flatten().count() as u32;
+        let shown = meshes.iter().flatten().cloned().collect();
+        chunk.meshes = Some(meshes);
+        for section in (0..SECTIONS).filter(|section| stale[*section]) {
+            self.mark_dirty(position, section);
+        }
+        Some(shown)
+    }
+
+    /// Adds a chunk read from disk and applies the decorations neighbours left for it.
+    /// Ignored if the chunk was loaded meanwhile.
+    pub fn insert_loaded(&mut self, position: (i64, i64), mut chunk: Chunk) {
+        if self.is_chunk_available(&position) {
+            return;
+        }
+        for placement in self.pending_placements.take(position) {
+            chunk.modified |= placement.apply(&mut chunk);
+        }
+        self.chunks.insert(position, chunk);
+    }
+
+    /// Adds a chunk generated on a worker task and applies its decorations, along with
+    /// the ones neighbours left for it. Ignored if the chunk was loaded meanwhile.
+    pub fn insert_generated(&mut self, position: (i64, i64), mut chunk: Chunk, placements: Vec<Placement>) {
+        if self.is_chunk_available(&position) {
+            return;
+        }
+        for placement in self.pending_placements.take(position) {
+            placement.apply(&mut chunk);
+        }
+        for placement in placements {
+            if placement.chunk == position {
+                placement.apply(&mut chunk);
+            } else if let Some(neighbour) = self.chunks.get_mut(&placement.chunk) {
+                if placement.apply(neighbour) {
+                    neighbour.modified = true;
+                    self.mark_block_dirty([
+                        placement.chunk.0 * 16 + placement.x as i64,
+                        placement.y as i64,
+                        placement.chunk.1 * 16 + placement.z as i64,
+                    ]);
                 }
-                let position = Vector2::new(j as f32, i as f32);
-                // North side
-                let position2 = position + Vector2::unit_x();
-                let chunk = world_lock.get_chunk(position2);
-                side_blocks[0] = *chunk.get_side_blocks(Cardinal::South);
-                
-                // South side
-                let position2 = position - Vector2::unit_x();
-                let chunk = world_lock.get_chunk(position2);
-                side_blocks[1] = *chunk.get_side_blocks(Cardinal::North);
-                
-                // East side
-                let position2 = position + Vector2::unit_y();
-                let chunk = world_lock.get_chunk(position2);
-                side_blocks[3] = *chunk.get_side_blocks(Cardinal::West);
-                
-                // West side
-                let position2 = position - Vector2::unit_y();
-                let chunk = world_lock.get_chunk(position2);
-                side_blocks[2] = *chunk.get_side_blocks(Cardinal::East);
-
-                let buffer_num = world_lock.get_chunk_mut(position).get_or_generate_mesh(texture_manager.clone(), &side_blocks, &device).await;
-                buffers.push(buffer_num);
-           }
-        }
-        world_lock.buffers_created = world_lock.chunks.iter().map(|x| x.1.buffers_created).sum();
-        log::info!("buffers created: {}", world_lock.buffers_created);
-        log::info!("returning mesh, done in {}ms", start.elapsed().as_secs_f32() * 1000.0);
-        *inactive_buffer.lock().await = buffers;
+            } else {
+                self.pending_placements.push(placement);
+            }
+        }
+        chunk.modified = true;
+        self.chunks.insert(position, chunk);
     }
 
-    fn is_chunk_available(&self, position: &(i64, i64)) -> bool {
-        match self.chunks.get(position) {
-            Some(_) => {
-                true
+    /// Sums the buffers every loaded chunk created, for the log.
+    pub fn count_buffers(&mut self) -> u32 {
+        self.buffers_created = self.chunks.values().map(|chunk| chunk.buffers_created).sum();
+        self.buffers_created
+    }
+
+    /// Unloads the chunks further than [`UNLOAD_MARGIN`] chunks outside the render
+    /// distance, then the furthest remaining ones until at most `max_chunks` are left.
+    /// `center` is the camera position in chunks, render x first. Modified chunks are
+    /// saved before they go, and are kept when there is nowhere to save them.
+    ///
+    /// Mesh buffers are not destroyed here, the mesh list being drawn may still use
+    /// them. They are freed when the last list holding them is dropped.
+    pub fn unload_chunks(&mut self, center: (f32, f32)) -> anyhow::Result<()> {
+        let distance = |position: &(i64, i64)| {
+            (position.1 as f32 - center.0).powi(2) + (position.0 as f32 - center.1).powi(2)
+        };
+        let mut by_distance: Vec<((i64, i64), f32)> = self
+            .chunks
+            .keys()
+            .map(|position| (*position, distance(position)))
+            .collect();
+        by_distance.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        // chunks in render distance and the ring around it the mesher reads from stay
+        let keep = ((self.render_distance + 1) as f32).powi(2);
+        let margin = ((self.render_distance + UNLOAD_MARGIN) as f32).powi(2);
+        let mut resident = self.chunks.len();
+        let mut unloaded = 0;
+        for (position, distance) in by_distance {
+            if distance <= keep || distance <= margin && resident <= self.max_chunks {
+                break;
             }
-            None => {
-                false
+            let chunk = &self.chunks[&position];
+            if chunk.modified {
+                let Some(storage) = self.storage.as_ref() else {
+                    continue;
+                };
+                storage.lock().unwrap().save_chunk(position, &chunk.to_bytes())?;
             }
+            self.chunks.remove(&position);
+            resident -= 1;
+            unloaded += 1;
+        }
+        if let Some(storage) = self.storage.as_ref() {
+            storage.lock().unwrap().flush()?;
         }
+        if resident > self.max_chunks {
+            log::warn!("{} chunks in render distance, more than the limit of {}", resident, self.max_chunks);
+        }
+        if unloaded > 0 {
+            log::info!("unloaded {} chunks, {} resident", unloaded, resident);
+        }
+        Ok(())
     }
-    fn get_chunk(&mut self, position: Vector2<f32>) -> &Chunk {
-        let i_position = (position.x as i64, position.y as i64);
-        if self.is_chunk_available(&i_position) {
-            return self.chunks.get(&i_position).unwrap();
+
+    /// Border slices of the four neighbours of a chunk, in the order the mesher expects.
+    fn side_blocks(&mut self, position: Vector2<f32>, side_blocks: &mut [[[Block; 16]; 256]; 4]) {
+        // North side
+        let position2 = position + Vector2::unit_x();
+        let chunk = self.get_chunk(position2);
+        side_blocks[0] = *chunk.get_side_blocks(Cardinal::South);
+
+        // South side
+        let position2 = position - Vector2::unit_x();
+        let chunk = self.get_chunk(position2);
+        side_blocks[1] = *chunk.get_side_blocks(Cardinal::North);
+
+        // East side
+        let position2 = position + Vector2::unit_y();
+        let chunk = self.get_chunk(position2);
+        side_blocks[3] = *chunk.get_side_blocks(Cardinal::West);
+
+        // West side
+        let position2 = position - Vector2::unit_y();
+        let chunk = self.get_chunk(position2);
+        side_blocks[2] = *chunk.get_side_blocks(Cardinal::East);
+    }
+
+    /// Changes the block at a world block position, `[x, y, z]` in the axes chunks
+    /// store blocks in, and returns the block that was there. The chunk holding the
+    /// block, and the neighbours sharing a face with it, are queued for remeshing, and
+    /// the block and its six neighbours get a block update on the next tick.
+    /// Returns `None` outside the world height.
+    pub fn set_block(&mut self, position: [i64; 3], block: Block) -> Option<Block> {
+        let [x, y, z] = position;
+        if !(0..256).contains(&y) {
+            return None;
         }
-        else {
-            self.generate_chunk(position);
-            return self.chunks.get(&i_position).unwrap();
+        let chunk_position = (x.div_euclid(16), z.div_euclid(16));
+        let (local_x, local_z) = (x.rem_euclid(16) as usize, z.rem_euclid(16) as usize);
+        let chunk = self.get_chunk_mut(Vector2::new(chunk_position.0 as f32, chunk_position.1 as f32));
+        let previous = chunk.get_block(local_x, y as usize, local_z);
+        if previous == block {
+            return Some(previous);
+        }
+        chunk.set_block(local_x, y as usize, local_z, block);
+        chunk.modified = true;
+        self.mark_block_dirty(position);
+        self.updates.notify(position);
+        Some(previous)
+    }
+
+    pub fn get_block(&mut self, position: [i64; 3]) -> Option<Block> {
+        let [x, y, z] = position;
+        if !(0..256).contains(&y) {
+            return None;
+        }
+        let chunk = self.get_chunk(Vector2::new(x.div_euclid(16) as f32, z.div_euclid(16) as f32));
+        Some(chunk.get_block(x.rem_euclid(16) as usize, y as usize, z.rem_euclid(16) as usize))
+    }
+
+    /// The block at a world block position if its chunk is in memory, without loading
+    /// or generating anything. Positions above and below the world are air.
+    pub fn loaded_block(&self, [x, y, z]: [i64; 3]) -> Option<Block> {
+        if !(0..256).contains(&y) {
+            return Some(Block::AIR);
         }
+        let chunk = self.chunks.get(&(x.div_euclid(16), z.div_euclid(16)))?;
+        Some(chunk.get_block(x.rem_euclid(16) as usize, y as usize, z.rem_euclid(16) as usize))
+    }
+
+    /// Finds the first block along a ray that `hits` accepts, at most `reach` blocks
+    /// away. `origin` and `direction` are in render coordinates, as the camera has
+    /// them. The block holding the origin is skipped, and the ray stops without a hit
+    /// when it reaches a chunk that is not in memory or goes below the world.
+    ///
+    /// Walks the grid one block at a time, always crossing the closest block boundary
+    /// next, so no block the ray passes through is missed.
+    pub fn raycast(
+        &self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        reach: f32,
+        hits: impl Fn(Block) -> bool,
+    ) -> Option<RayHit> {
+        if direction.magnitude2() == 0.0 {
+            return None;
+        }
+        let direction = direction.normalize();
+        // render axes to storage axes
+        let origin = [origin.z, origin.y + 60.0, origin.x];
+        let direction = [direction.z, direction.y, direction.x];
+
+        let mut block = origin.map(|coordinate| coordinate.floor() as i64);
+        let step = direction.map(|component| if component < 0.0 { -1 } else { 1 });
+        // distance along the ray between boundaries, and to the next boundary
+        let delta = direction.map(|component| 1.0 / component.abs());
+        let mut next = [0.0; 3];
+        for axis in 0..3 {
+            let boundary = if step[axis] > 0 { block[axis] as f32 + 1.0 } else { block[axis] as f32 };
+            next[axis] = if direction[axis] == 0.0 {
+                f32::INFINITY
+            } else {
+                (boundary - origin[axis]) / direction[axis]
+            };
+        }
+
+        loop {
+            let axis = (0..3).min_by(|a, b| next[*a].total_cmp(&next[*b])).unwrap();
+            let distance = next[axis];
+            if distance > reach {
+                return None;
+            }
+            block[axis] += step[axis];
+            next[axis] += delta[axis];
+            if block[1] < 0 {
+                return None;
+            }
+            let found = self.loaded_block(block)?;
+            if hits(found) {
+                let face = match (axis, step[axis] > 0) {
+                    (0, true) => Cardinal::South,
+                    (0, false) => Cardinal::North,
+                    (1, true) => Cardinal::Down,
+                    (1, false) => Cardinal::Up,
+                    (_, true) => Cardinal::East,
+                    (_, false) => Cardinal::West,
+                };
+                return Some(RayHit { block, face, distance });
+            }
+        }
+    }
+
+    /// Queues the section holding a block for remeshing, along with the sections next
+    /// to it whose faces the block can hide or reveal.
+    fn mark_block_dirty(&mut self, [x, y, z]: [i64; 3]) {
+        let chunk = (x.div_euclid(16), z.div_euclid(16));
+        let section = y as usize / SECTION_SIZE;
+        let (local_x, local_y, local_z) = (x.rem_euclid(16), y as usize % SECTION_SIZE, z.rem_euclid(16));
+        self.mark_dirty(chunk, section);
+        if local_y == 0 && section > 0 {
+            self.mark_dirty(chunk, section - 1);
+        }
+        if local_y == SECTION_SIZE - 1 && section + 1 < SECTIONS {
+            self.mark_dirty(chunk, section + 1);
+        }
+        if local_x == 0 {
+            self.mark_dirty((chunk.0 - 1, chunk.1), section);
+        }
+        if local_x == 15 {
+            self.mark_dirty((chunk.0 + 1, chunk.1), section);
+        }
+        if local_z == 0 {
+            self.mark_dirty((chunk.0, chunk.1 - 1), section);
+        }
+        if local_z == 15 {
+            self.mark_dirty((chunk.0, chunk.1 + 1), section);
+        }
+    }
+
+    /// Queues a section of a loaded chunk to be remeshed. Chunks that are not meshed
+    /// yet are meshed from scratch when they come into view, so they are skipped.
+    fn mark_dirty(&mut self, position: (i64, i64), section: usize) {
+        let Some(chunk) = self.chunks.get_mut(&position) else {
+            return;
+        };
+        if chunk.meshes.is_some() && chunk.dirty & 1 << section == 0 {
+            chunk.dirty |= 1 << section;
+            self.remesh_queue.push_back((position, section));
+        }
+    }
+
+    pub fn has_dirty_chunks(&self) -> bool {
+        !self.remesh_queue.is_empty()
+    }
+
+    /// Rebuilds the sections queued by [`World::set_block`] and sends them to the
+    /// renderer, leaving every other mesh alone.
+    pub async fn remesh_dirty(
+        registry: Arc<BlockRegistry>,
+        device: Arc<wgpu::Device>,
+        tint_layout: Arc<wgpu::BindGroupLayout>,
+        world: Arc<Mutex<World>>,
+        updates: UnboundedSender<MeshUpdate>,
+    ) {
+        let start = Instant::now();
+        let mut world_lock = world.lock().await;
+        let meshing_mode = world_lock.meshing_mode;
+        let mut side_blocks = [[[Block::STONE; 16]; 256]; 4];
+        let mut remeshed = 0;
+        while let Some((position, section)) = world_lock.remesh_queue.pop_front() {
+            let chunk_position = Vector2::new(position.0 as f32, position.1 as f32);
+            world_lock.side_blocks(chunk_position, &mut side_blocks);
+            let Some(chunk) = world_lock.chunks.get_mut(&position) else {
+                continue;
+            };
+            chunk.dirty &= !(1 << section);
+            let mesh = chunk
+                .generate_section_mesh(section, &registry, &side_blocks, &device, &tint_layout, meshing_mode)
+                .await;
+            let _ = updates.send(MeshUpdate::Section { chunk: position, section, mesh });
+            remeshed += 1;
+        }
+        log::info!("remeshed {} sections in {}ms", remeshed, start.elapsed().as_secs_f32() * 1000.0);
+    }
+
+    fn is_chunk_available(&self, position: &(i64, i64)) -> bool {
+        self.chunks.contains_key(position)
+    }
+    fn get_chunk(&mut self, position: Vector2<f32>) -> &Chunk {
+        self.get_chunk_mut(position)
     }
     fn get_chunk_mut(&mut self, position: Vector2<f32>) -> &mut Chunk {
         let i_position = (position.x as i64, position.y as i64);
-        if self.is_chunk_available(&i_position) {
-            return self.chunks.get_mut(&i_position).unwrap();
-        }
-        else {
+        if !self.is_chunk_available(&i_position) && !self.load_chunk(position) {
             self.generate_chunk(position);
-            return self.chunks.get_mut(&i_position).unwrap();
         }
-    }   
-    pub fn generate_chunk(&mut self, at_position: Vector2<f32>) {
-        let perlin = Perlin::new(self.seed[0] as u32);
-        let mut chunk = Chunk::new(at_position);
-        for x in 0..16 {
-            for z in 0..16 {
-                let y =
-                    10.0 * perlin.get([
-                        3.0e-1 * (x as f32 / 16.0 + chunk.position.x) as f64,
-                        3.0e-1 * (z as f32 / 16.0 + chunk.position.y) as f64,
-                    ]) 
-                    + 50.0
-                        * perlin.get([
-                            5.0e-2 * (x as f32 / 16.0 + chunk.position.x) as f64,
-                            5.0e-2 * (z as f32 / 16.0 + chunk.position.y) as f64,
-                        ])
-                    + 75.0
-                        * perlin.get([
-                            5.0e-3 * (x as f32 / 16.0 + chunk.position.x) as f64,
-                            5.0e-3 * (z as f32 / 16.0 + chunk.position.y) as f64,
-                        ])
-                    + 100.0
-                        * perlin.get([
-                            5.0e-7 * (x as f32 / 16.0 + chunk.position.x) as f64,
-                            5.0e-7 * (z as f32 / 16.0 + chunk.position.y) as f64,
-                        ]);
-                            
-                // println!("{:?}", y);
-                let y = (y / (10.0 + 50.0 + 75.0 + 100.0) + 1.0) / 2.0;
-                let y = (y).powf(2.5);
-                let y = (y * 190.0 + 20.0).floor().clamp(0.0, 255.0);
-                let y = y as usize;
-                chunk.block_data[y][x][z] = Block::Grass;
-                for height in (0..y).rev() {
-                    chunk.block_data[height][x][z] = Block::Dirt;
-                    if y < 4 {
-                        chunk.block_data[height][x][z] = Block::Stone;
-                    } else if height < y - 4 {
-                        chunk.block_data[height][x][z] = Block::Stone;
-                    }
-                }
+        self.chunks.get_mut(&i_position).unwrap()
+    }
+    /// Tries to read the chunk from disk, returns false if it has never been saved.
+    fn load_chunk(&mut self, position: Vector2<f32>) -> bool {
+        let Some(storage) = self.storage.as_ref() else {
+            return false;
+        };
+        let i_position = (position.x as i64, position.y as i64);
+        match read_chunk(storage, &self.noise, i_position) {
+            Some(chunk) => {
+                self.insert_loaded(i_position, chunk);
+                true
             }
+            None => false,
         }
-        chunk.block_data[0] = [[Block::Bedrock; 16]; 16];
+    }
+    pub fn generate_chunk(&mut self, at_position: Vector2<f32>) {
         let position = (at_position.x as i64, at_position.y as i64);
-        self.chunks.insert(position,chunk);
+        let (chunk, placements) = generation::generate_decorated(&self.noise, position);
+        self.insert_generated(position, chunk, placements);
     }
 }
 
-fn north_face(position: Vector3<f32>, texture_id: f32) -> [Vertex; 6] {
-    // [x, y, z]
-
-    let y = position.y - 60.0;
-    let positions = [
-        // North bottom left (0)
-        [position.z, y, position.x],
-        // North top right (1)
-        [position.z + 1.0, y + 1.0, position.x],
-        // North bottom right(2)
-        [position.z + 1.0, y, position.x],
-        // North top left (3)
-        [position.z, y + 1.0, position.x],
-        // South top left (4)
-        [position.z + 1.0, y + 1.0, position.x + 1.0],
-        // South top right (5)
-        [position.z, y + 1.0, position.x + 1.0],
-        // South bottom left (6)
-        [position.z + 1.0, y, position.x + 1.0],
-        // South bottom right (7)
-        [position.z, y, position.x + 1.0],
-    ];
-    [
-        //first triangle
-        Vertex {
-            position: positions[6],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[5],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[7],
-            tex_coord: [1.0, 1.0, texture_id],
-        },
-        // second triangle
-        Vertex {
-            position: positions[6],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[4],
-            tex_coord: [0.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[5],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-    ]
-}
-fn south_face(position: Vector3<f32>, texture_id: f32) -> [Vertex; 6] {
-    // [x, y, z]
-    let y = position.y - 60.0;
-    let positions = [
-        // North bottom left (0)
-        [position.z, y, position.x],
-        // North top right (1)
-        [position.z + 1.0, y + 1.0, position.x],
-        // North bottom right(2)
-        [position.z + 1.0, y, position.x],
-        // North top left (3)
-        [position.z, y + 1.0, position.x],
-        // South top left (4)
-        [position.z + 1.0, y + 1.0, position.x + 1.0],
-        // South top right (5)
-        [position.z, y + 1.0, position.x + 1.0],
-        // South bottom left (6)
-        [position.z + 1.0, y, position.x + 1.0],
-        // South bottom right (7)
-        [position.z, y, position.x + 1.0],
-    ];
-    [
-        //first triangle
-        Vertex {
-            position: positions[0],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[3],
-            tex_coord: [0.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[1],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-        // second triangle
-        Vertex {
-            position: positions[0],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[1],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[2],
-            tex_coord: [1.0, 1.0, texture_id],
-        },
-    ]
-}
-fn east_face(position: Vector3<f32>, texture_id: f32) -> [Vertex; 6] {
-    // [x, y, z]
-    let y = position.y - 60.0;
-    let positions = [
-        // North bottom left (0)
-        [position.z, y, position.x],
-        // North top right (1)
-        [position.z + 1.0, y + 1.0, position.x],
-        // North bottom right(2)
-        [position.z + 1.0, y, position.x],
-        // North top left (3)
-        [position.z, y + 1.0, position.x],
-        // South top left (4)
-        [position.z + 1.0, y + 1.0, position.x + 1.0],
-        // South top right (5)
-        [position.z, y + 1.0, position.x + 1.0],
-        // South bottom left (6)
-        [position.z + 1.0, y, position.x + 1.0],
-        // South bottom right (7)
-        [position.z, y, position.x + 1.0],
-    ];
-    [
-        //first triangle
-        Vertex {
-            position: positions[7],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[3],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[0],
-            tex_coord: [1.0, 1.0, texture_id],
-        },
-        //second triangle
-        Vertex {
-            position: positions[7],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[5],
-            tex_coord: [0.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[3],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-    ]
-}
-fn west_face(position: Vector3<f32>, texture_id: f32) -> [Vertex; 6] {
-    // [x, y, z]
-    let y = position.y - 60.0;
-    let positions = [
-        // North bottom left (0)
-        [position.z, y, position.x],
-        // North top right (1)
-        [position.z + 1.0, y + 1.0, position.x],
-        // North bottom right(2)
-        [position.z + 1.0, y, position.x],
-        // North top left (3)
-        [position.z, y + 1.0, position.x],
-        // South top left (4)
-        [position.z + 1.0, y + 1.0, position.x + 1.0],
-        // South top right (5)
-        [position.z, y + 1.0, position.x + 1.0],
-        // South bottom left (6)
-        [position.z + 1.0, y, position.x + 1.0],
-        // South bottom right (7)
-        [position.z, y, position.x + 1.0],
-    ];
-    [
-        //first triangle
-        Vertex {
-            position: positions[2],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[4],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[6],
-            tex_coord: [1.0, 1.0, texture_id],
-        },
-        //second triangle
-        Vertex {
-            position: positions[2],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[1],
-            tex_coord: [0.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[4],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-    ]
-}
-fn top_face(position: Vector3<f32>, texture_id: f32) -> [Vertex; 6] {
-    // [x, y, z]
-    let y = position.y - 60.0;
-    let positions = [
-        // North bottom left (0)
-        [position.z, y, position.x],
-        // North top right (1)
-        [position.z + 1.0, y + 1.0, position.x],
-        // North bottom right(2)
-        [position.z + 1.0, y, position.x],
-        // North top left (3)
-        [position.z, y + 1.0, position.x],
-        // South top left (4)
-        [position.z + 1.0, y + 1.0, position.x + 1.0],
-        // South top right (5)
-        [position.z, y + 1.0, position.x + 1.0],
-        // South bottom left (6)
-        [position.z + 1.0, y, position.x + 1.0],
-        // South bottom right (7)
-        [position.z, y, position.x + 1.0],
-    ];
-    [
-        //first triangle
-        Vertex {
-            position: positions[3],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[5],
-            tex_coord: [0.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[4],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-        //second triangle
-        Vertex {
-            position: positions[3],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[4],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[1],
-            tex_coord: [1.0, 1.0, texture_id],
-        },
-    ]
-}
-fn bottom_face(position: Vector3<f32>, texture_id: f32) -> [Vertex; 6] {
-    // [x, y, z]
-    let y = position.y - 60.0;
-    let positions = [
-        // North bottom left (0)
-        [position.z, y, position.x],
-        // North top right (1)
-        [position.z + 1.0, y + 1.0, position.x],
-        // North bottom right(2)
-        [position.z + 1.0, y, position.x],
-        // North top left (3)
-        [position.z, y + 1.0, position.x],
-        // South top left (4)
-        [position.z + 1.0, y + 1.0, position.x + 1.0],
-        // South top right (5)
-        [position.z, y + 1.0, position.x + 1.0],
-        // South bottom left (6)
-        [position.z + 1.0, y, position.x + 1.0],
-        // South bottom right (7)
-        [position.z, y, position.x + 1.0],
-    ];
-    [
-        //first triangle
-        Vertex {
-            position: positions[2],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[7],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[0],
-            tex_coord: [1.0, 1.0, texture_id],
-        },
-        //second triangle
-        Vertex {
-            position: positions[2],
-            tex_coord: [0.0, 1.0, texture_id],
-        },
-        Vertex {
-            position: positions[6],
-            tex_coord: [0.0, 0.0, texture_id],
-        },
-        Vertex {
-            position: positions[7],
-            tex_coord: [1.0, 0.0, texture_id],
-        },
-    ]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A world of empty chunks around the origin holding `blocks`, as stone.
+    fn world_with(blocks: &[[i64; 3]]) -> World {
+        let mut world = World::new("raycast".to_string(), 2);
+        for x in -1..=1 {
+            for z in -1..=1 {
+                world.chunks.insert((x, z), Chunk::new((x as f32, z as f32)));
+            }
+        }
+        for &block in blocks {
+            world.set_block(block, Block::STONE);
+        }
+        world
+    }
+
+    /// Casts a ray given in storage axes, hitting anything but air.
+    fn cast(world: &World, [x, y, z]: [f32; 3], [dx, dy, dz]: [f32; 3], reach: f32) -> Option<RayHit> {
+        world.raycast(Point3::new(z, y - 60.0, x), Vector3::new(dz, dy, dx), reach, |block| block != Block::AIR)
+    }
+
+    fn assert_hit(hit: Option<RayHit>, block: [i64; 3], face: Cardinal, distance: f32) {
+        let hit = hit.expect("the ray missed");
+        assert_eq!((hit.block, hit.face), (block, face));
+        assert!((hit.distance - distance).abs() < 1e-4, "distance {} instead of {}", hit.distance, distance);
+    }
+
+    #[test]
+    fn hits_the_face_the_ray_enters() {
+        let origin = [8.5, 20.5, 8.5];
+        for (direction, block, face) in [
+            ([1.0, 0.0, 0.0], [11, 20, 8], Cardinal::South),
+            ([-1.0, 0.0, 0.0], [5, 20, 8], Cardinal::North),
+            ([0.0, 1.0, 0.0], [8, 23, 8], Cardinal::Down),
+            ([0.0, -1.0, 0.0], [8, 17, 8], Cardinal::Up),
+            ([0.0, 0.0, 1.0], [8, 20, 11], Cardinal::East),
+            ([0.0, 0.0, -1.0], [8, 20, 5], Cardinal::West),
+        ] {
+            let world = world_with(&[block]);
+            assert_hit(cast(&world, origin, direction, 8.0), block, face, 2.5);
+            // the face the ray came through is the one a placed block goes on
+            let [dx, dy, dz] = face.normal();
+            let placed = cast(&world, origin, direction, 8.0).unwrap().adjacent();
+            assert_eq!(placed, [block[0] + dx, block[1] + dy, block[2] + dz]);
+        }
+    }
+
+    #[test]
+    fn crosses_chunk_borders() {
+        let world = world_with(&[[17, 10, 3], [3, 10, -2]]);
+        assert_hit(cast(&world, [14.5, 10.5, 3.5], [1.0, 0.0, 0.0], 8.0), [17, 10, 3], Cardinal::South, 2.5);
+        assert_hit(cast(&world, [3.5, 10.5, 1.5], [0.0, 0.0, -1.0], 8.0), [3, 10, -2], Cardinal::West, 2.5);
+    }
+
+    #[test]
+    fn negative_coordinates() {
+        let world = world_with(&[[-1, 10, -5]]);
+        assert_hit(cast(&world, [-0.5, 10.5, -0.5], [0.0, 0.0, -1.0], 8.0), [-1, 10, -5], Cardinal::West, 3.5);
+        assert_hit(cast(&world, [-9.5, 10.5, -4.5], [1.0, 0.0, 0.0], 16.0), [-1, 10, -5], Cardinal::South, 8.5);
+
+        // a sloped ray onto a floor, entering it at x -4.45 and z -8.2
+        let floor: Vec<_> = (-16..16).flat_map(|x| (-16..16).map(move |z| [x, 9, z])).collect();
+        let world = world_with(&floor);
+        let direction = Vector3::new(0.3_f32, -1.0, -0.2);
+        assert_hit(
+            cast(&world, [-5.2, 12.5, -7.7], direction.into(), 8.0),
+            [-5, 9, -9],
+            Cardinal::Up,
+            2.5 * direction.magnitude(),
+        );
+    }
+
+    #[test]
+    fn stops_at_reach() {
+        let world = world_with(&[[8, 20, 11]]);
+        assert!(cast(&world, [8.5, 20.5, 8.5], [0.0, 0.0, 1.0], 2.4).is_none());
+        assert!(cast(&world, [8.5, 20.5, 8.5], [0.0, 0.0, 1.0], 2.6).is_some());
+    }
+
+    #[test]
+    fn skips_the_origin_and_stops_at_unloaded_chunks() {
+        let world = world_with(&[[8, 20, 8], [8, 20, 10]]);
+        assert_hit(cast(&world, [8.5, 20.5, 8.5], [0.0, 0.0, 1.0], 8.0), [8, 20, 10], Cardinal::East, 1.5);
+        // nothing but chunks that are not in memory past z 32
+        assert!(cast(&world, [8.5, 20.5, 20.5], [0.0, 0.0, 1.0], 64.0).is_none());
+        assert!(cast(&world, [8.5, 20.5, 8.5], [0.0, 0.0, 0.0], 8.0).is_none());
+    }
 }